@@ -0,0 +1,69 @@
+//! Images stored in ZIP archives, such as the output of document scanners.
+
+use crate::{keep_going, Page, ScanError, ScanOptions};
+use image::ImageReader;
+use std::io::{Cursor, Read, Seek};
+use zip::ZipArchive;
+
+/// Extracts the images of the entries accepted by `include`, in entry name order.
+///
+/// Entries that cannot be decoded as images are skipped. Images larger than the allowed pixels,
+/// or entries adding up to more than the allowed extracted size, fail the extraction, unless in
+/// best effort mode.
+pub(crate) fn extract_images(
+    reader: impl Read + Seek,
+    options: &ScanOptions,
+    include: impl Fn(&str) -> bool,
+) -> Result<Vec<Page>, ScanError> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| ScanError::Archive(e.to_string()))?;
+
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && include(name))
+        .map(str::to_string)
+        .collect();
+    names.sort();
+
+    let mut pages = Vec::new();
+    let mut remaining = options.max_archive_size;
+    for name in names {
+        // the sizes of the headers can lie, only count what is actually inflated
+        let mut buffer = Vec::new();
+        let read = archive.by_name(&name).and_then(|entry| {
+            entry
+                .take(remaining.saturating_add(1))
+                .read_to_end(&mut buffer)
+                .map_err(Into::into)
+        });
+        if let Err(e) = read {
+            keep_going(&mut pages, ScanError::Archive(e.to_string()), options)?;
+            continue;
+        }
+        let Some(left) = remaining.checked_sub(buffer.len() as u64) else {
+            let max_size = options.max_archive_size;
+            keep_going(&mut pages, ScanError::ArchiveTooLarge { max_size }, options)?;
+            break;
+        };
+        remaining = left;
+
+        let Ok(reader) = ImageReader::new(Cursor::new(buffer)).with_guessed_format() else {
+            continue;
+        };
+        match crate::decode_image_file(reader, options) {
+            Ok(image) => pages.push(image.into()),
+            Err(e @ ScanError::ImageTooLarge { .. }) => keep_going(&mut pages, e, options)?,
+            Err(_) => continue,
+        }
+        if pages.len() > options.max_pages {
+            let error = ScanError::TooManyPages {
+                pages: pages.len(),
+                max_pages: options.max_pages,
+            };
+            pages.pop();
+            keep_going(&mut pages, error, options)?;
+            break;
+        }
+    }
+
+    Ok(pages)
+}
@@ -0,0 +1,221 @@
+//! Check digit validation for the symbologies whose checksum is not enforced by the decoder, and
+//! for the EAN/UPC ones, whose check digit the decoder enforces, to report it explicitly.
+
+use rxing::{oned::convertUPCEtoUPCA, BarcodeFormat};
+
+/// Codabar characters, indexed by their checksum value.
+const CODABAR_ALPHABET: &str = "0123456789-$:/.+ABCD";
+
+/// Validates the checksum of the decoded data, for formats that have one.
+///
+/// Codabar data must still carry its start and stop characters. Since its check character is
+/// optional, a Codabar code is only reported when the check holds: a mismatch could as well be a
+/// code printed without one, and is reported as unknown rather than invalid.
+pub(crate) fn checksum_valid(format: &BarcodeFormat, data: &str) -> Option<bool> {
+    match format {
+        BarcodeFormat::ITF => Some(mod10_valid(data)),
+        BarcodeFormat::CODABAR => codabar_valid(data).then_some(true),
+        BarcodeFormat::EAN_8 => Some(gtin_valid(data, 8)),
+        BarcodeFormat::EAN_13 => Some(gtin_valid(data, 13)),
+        BarcodeFormat::UPC_A => Some(gtin_valid(data, 12)),
+        BarcodeFormat::UPC_E => Some(upc_e_valid(data)),
+        _ => None,
+    }
+}
+
+/// Validates the GS1 mod 10 check digit terminating the data, as used by ITF-14 and the GTINs.
+fn mod10_valid(data: &str) -> bool {
+    let Some(digits) = data
+        .chars()
+        .map(|c| c.to_digit(10))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    let Some((check, payload)) = digits.split_last() else {
+        return false;
+    };
+
+    // weights alternate 3, 1, 3, ... starting from the digit next to the check digit
+    let sum: u32 = payload
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| if i % 2 == 0 { digit * 3 } else { *digit })
+        .sum();
+
+    (10 - sum % 10) % 10 == *check
+}
+
+/// Validates a GTIN of `len` digits, check digit included.
+fn gtin_valid(data: &str, len: usize) -> bool {
+    data.len() == len && mod10_valid(data)
+}
+
+/// Validates the check digit of a UPC-E code, which is the one of the UPC-A code it abbreviates.
+fn upc_e_valid(data: &str) -> bool {
+    // number system, six digits and check digit
+    if data.len() != 8 || !data.bytes().all(|byte| byte.is_ascii_digit()) {
+        return false;
+    }
+
+    convertUPCEtoUPCA(data).is_some_and(|upc_a| mod10_valid(&upc_a))
+}
+
+/// Validates the optional mod 16 Codabar check character: the values of all the characters,
+/// start and stop included, must add up to a multiple of 16.
+fn codabar_valid(data: &str) -> bool {
+    let values = data
+        .to_ascii_uppercase()
+        .chars()
+        .map(|c| CODABAR_ALPHABET.find(c))
+        .collect::<Option<Vec<_>>>();
+
+    match values {
+        // start, at least one data character, check character and stop
+        Some(values) if values.len() >= 4 => values.iter().sum::<usize>() % 16 == 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn itf_14_check_digit() {
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::ITF, "00012345678905"),
+            Some(true)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::ITF, "15400141288763"),
+            Some(true)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::ITF, "00012345678906"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::ITF, "0001234567890A"),
+            Some(false)
+        );
+        assert_eq!(checksum_valid(&BarcodeFormat::ITF, ""), Some(false));
+    }
+
+    #[test]
+    fn gtin_check_digits() {
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::EAN_8, "96385074"),
+            Some(true)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::EAN_8, "96385075"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::EAN_13, "4006381333931"),
+            Some(true)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::EAN_13, "4006381333932"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_A, "036000291452"),
+            Some(true)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_A, "036000291453"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn gtin_of_a_wrong_length_or_with_other_characters() {
+        // a valid UPC-A check digit, but not 13 digits long
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::EAN_13, "036000291452"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::EAN_8, "4006381333931"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_A, "96385074"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_A, "03600029145X"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::EAN_13, "4006381 33931"),
+            Some(false)
+        );
+        assert_eq!(checksum_valid(&BarcodeFormat::EAN_8, ""), Some(false));
+    }
+
+    #[test]
+    fn upc_e_check_digit_of_the_expanded_upc_a() {
+        assert_eq!(
+            convertUPCEtoUPCA("01234565").as_deref(),
+            Some("012345000065")
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_E, "01234565"),
+            Some(true)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_E, "01234566"),
+            Some(false)
+        );
+        // the check digit is the one of 042100005264, not of the 8 digits themselves
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_E, "04252614"),
+            Some(true)
+        );
+        // 01234503 would check out as 8 digits, but not as 012000003453
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_E, "01234503"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_E, "0123456"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_E, "012345650"),
+            Some(false)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::UPC_E, "0123456A"),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn codabar_check_character() {
+        // A = 16, 1 + 2 + 3 + 4 = 10, check 5, B = 17: 48
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::CODABAR, "A12345B"),
+            Some(true)
+        );
+        assert_eq!(
+            checksum_valid(&BarcodeFormat::CODABAR, "a12345b"),
+            Some(true)
+        );
+        // C = 18, - = 10, $ = 11, check 6, D = 19: 64
+        assert_eq!(checksum_valid(&BarcodeFormat::CODABAR, "C-$6D"), Some(true));
+    }
+
+    #[test]
+    fn codabar_without_check_character_is_unknown() {
+        // a wrong check character cannot be told apart from a missing one
+        assert_eq!(checksum_valid(&BarcodeFormat::CODABAR, "A12346B"), None);
+        assert_eq!(checksum_valid(&BarcodeFormat::CODABAR, "A1234B"), None);
+        assert_eq!(checksum_valid(&BarcodeFormat::CODABAR, "A1B"), None);
+        assert_eq!(checksum_valid(&BarcodeFormat::CODABAR, "A12*45B"), None);
+    }
+}
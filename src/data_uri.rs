@@ -0,0 +1,146 @@
+//! Files sent as `data:` URIs, as browsers produce for captured images.
+
+use crate::{is_supported_mime_type, ScanError};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Decodes a base64 data URI such as `data:image/png;base64,...`, returning its MIME type and
+/// content.
+///
+/// Fails with [`ScanError::UnsupportedFileType`] when the declared MIME type cannot be scanned,
+/// or [`ScanError::PdfNotCompiled`] for PDFs without the `pdf` feature.
+/// The content is still sniffed when scanned, the declared type is not trusted.
+pub fn decode_data_uri(uri: &str) -> Result<(String, Vec<u8>), ScanError> {
+    let uri = uri.trim();
+    if !uri
+        .get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+    {
+        return Err(ScanError::DataUri("missing data: scheme".to_string()));
+    }
+    let Some((header, payload)) = uri[5..].split_once(',') else {
+        return Err(ScanError::DataUri("missing data".to_string()));
+    };
+
+    // the media type can carry parameters, base64 is always the last one
+    let mut parameters = header.split(';');
+    let mime_type = parameters.next().unwrap_or_default().to_ascii_lowercase();
+    if !parameters
+        .next_back()
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("base64"))
+    {
+        return Err(ScanError::DataUri(
+            "only base64 data is supported".to_string(),
+        ));
+    }
+    if !is_supported_mime_type(&mime_type) {
+        return Err(ScanError::unsupported(mime_type));
+    }
+
+    let data = STANDARD
+        .decode(payload)
+        .map_err(|e| ScanError::DataUri(e.to_string()))?;
+
+    Ok((mime_type, data))
+}
+
+/// Encodes `data` as a base64 data URI of the given MIME type.
+pub(crate) fn encode_data_uri(mime_type: &str, data: &[u8]) -> String {
+    format!("data:{mime_type};base64,{}", STANDARD.encode(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_base64_data() {
+        let (mime_type, data) = decode_data_uri(" data:image/png;base64,aGVsbG8= ").unwrap();
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn missing_scheme() {
+        assert!(matches!(
+            decode_data_uri("image/png;base64,aGVsbG8="),
+            Err(ScanError::DataUri(_))
+        ));
+        assert!(matches!(decode_data_uri("dat"), Err(ScanError::DataUri(_))));
+        assert!(matches!(
+            decode_data_uri("data:image/png;base64"),
+            Err(ScanError::DataUri(_))
+        ));
+    }
+
+    #[test]
+    fn upper_case_scheme_and_type() {
+        let (mime_type, data) = decode_data_uri("DATA:IMAGE/PNG;BASE64,aGVsbG8=").unwrap();
+        assert_eq!(mime_type, "image/png");
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn parameters_before_base64() {
+        let (mime_type, data) =
+            decode_data_uri("data:image/jpeg;name=photo.jpg;charset=binary;base64,aGVsbG8=")
+                .unwrap();
+        assert_eq!(mime_type, "image/jpeg");
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn only_base64_is_supported() {
+        assert!(matches!(
+            decode_data_uri("data:image/png,hello"),
+            Err(ScanError::DataUri(_))
+        ));
+        assert!(matches!(
+            decode_data_uri("data:image/png;base64;charset=utf-8,aGVsbG8="),
+            Err(ScanError::DataUri(_))
+        ));
+    }
+
+    #[test]
+    fn unsupported_mime_type() {
+        assert!(matches!(
+            decode_data_uri("data:text/plain;base64,aGVsbG8="),
+            Err(ScanError::UnsupportedFileType(mime_type)) if mime_type == "text/plain"
+        ));
+        assert!(matches!(
+            decode_data_uri("data:;base64,aGVsbG8="),
+            Err(ScanError::UnsupportedFileType(_))
+        ));
+    }
+
+    #[cfg(not(feature = "pdf"))]
+    #[test]
+    fn pdf_without_the_pdf_feature() {
+        assert!(matches!(
+            decode_data_uri("data:application/pdf;base64,aGVsbG8="),
+            Err(ScanError::PdfNotCompiled)
+        ));
+    }
+
+    #[test]
+    fn bad_base64() {
+        assert!(matches!(
+            decode_data_uri("data:image/png;base64,aGVsbG8"),
+            Err(ScanError::DataUri(_))
+        ));
+        assert!(matches!(
+            decode_data_uri("data:image/png;base64,a*bc"),
+            Err(ScanError::DataUri(_))
+        ));
+    }
+
+    #[test]
+    fn encodes_what_it_decodes() {
+        let data = [0, 1, 2, 253, 254, 255];
+        let uri = encode_data_uri("image/png", &data);
+        assert!(uri.starts_with("data:image/png;base64,"));
+        assert_eq!(
+            decode_data_uri(&uri).unwrap(),
+            ("image/png".to_string(), data.to_vec())
+        );
+    }
+}
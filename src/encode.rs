@@ -0,0 +1,23 @@
+//! Encoding text into barcode images, the inverse of the scan.
+
+use crate::ScanError;
+use image::DynamicImage;
+use rxing::{BarcodeFormat, MultiFormatWriter, Writer};
+
+/// Encodes `text` into a black on white barcode image of the given format.
+///
+/// The image is at least `width` by `height` pixels, larger when the symbol does not fit.
+pub fn encode_barcode(
+    text: &str,
+    format: BarcodeFormat,
+    width: u32,
+    height: u32,
+) -> Result<DynamicImage, ScanError> {
+    let width = i32::try_from(width).map_err(|e| ScanError::Encode(e.to_string()))?;
+    let height = i32::try_from(height).map_err(|e| ScanError::Encode(e.to_string()))?;
+
+    MultiFormatWriter
+        .encode(text, &format, width, height)
+        .map(DynamicImage::from)
+        .map_err(|e| ScanError::Encode(e.to_string()))
+}
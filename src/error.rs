@@ -0,0 +1,197 @@
+use image::ImageError;
+#[cfg(feature = "pdf")]
+use pdfium_render::prelude::PdfiumError;
+use std::{error::Error, fmt, io, path::Path, sync::Arc};
+
+/// Errors that can occur while scanning a file.
+#[derive(Debug)]
+pub enum ScanError {
+    /// The file could not be opened or read.
+    Read(io::Error),
+    /// The file type could not be detected.
+    UnknownFileType,
+    /// The file type was detected but is not supported. Carries the detected MIME type.
+    UnsupportedFileType(String),
+    /// The PDF could not be rendered.
+    #[cfg(feature = "pdf")]
+    Pdf(PdfiumError),
+    /// The pdfium library could not be loaded, so no PDF can be scanned. Images still can.
+    #[cfg(feature = "pdf")]
+    PdfiumUnavailable(PdfiumError),
+    /// The file is a PDF, but the crate was built without the `pdf` feature.
+    PdfNotCompiled,
+    /// The PDF has no pages, so there is nothing to scan.
+    EmptyDocument,
+    /// The PDF has more pages than allowed.
+    TooManyPages { pages: usize, max_pages: usize },
+    /// The requested page, counted from 0, is past the end of the document.
+    PageOutOfRange { index: usize, pages: usize },
+    /// The file does not hold the expected number of barcodes.
+    UnexpectedCount { expected: usize, found: usize },
+    /// The file holds more barcodes than allowed.
+    TooManyBarcodes { found: usize, max_results: usize },
+    /// The image could not be decoded.
+    Image(ImageError),
+    /// The image has more pixels than allowed.
+    ImageTooLarge {
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    },
+    /// The archive holding the images could not be read.
+    Archive(String),
+    /// The data URI is malformed.
+    DataUri(String),
+    /// The archive extracts to more bytes than allowed.
+    ArchiveTooLarge { max_size: u64 },
+    /// The text could not be encoded in the requested format.
+    Encode(String),
+    /// The frames of the video clip could not be extracted.
+    Video(String),
+    /// The resolutions to render the PDF pages at are out of range, or too many.
+    RenderDpis(String),
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::Read(e) => write!(f, "Failed to read file: {e}"),
+            ScanError::UnknownFileType => write!(f, "Unknown file type"),
+            ScanError::UnsupportedFileType(mime_type) => {
+                write!(f, "Unexpected file type: {mime_type}")
+            }
+            // the pdfium errors only implement a multi-line Display
+            #[cfg(feature = "pdf")]
+            ScanError::Pdf(e) => write!(f, "Failed to extract images from PDF: {e:?}"),
+            #[cfg(feature = "pdf")]
+            ScanError::PdfiumUnavailable(e) => {
+                write!(
+                    f,
+                    "PDF support is unavailable, the pdfium library could not be loaded: {e:?}"
+                )
+            }
+            ScanError::PdfNotCompiled => {
+                write!(
+                    f,
+                    "PDF support is not compiled in, the pdf feature is disabled"
+                )
+            }
+            ScanError::EmptyDocument => write!(f, "The document has no pages"),
+            ScanError::TooManyPages { pages, max_pages } => {
+                write!(
+                    f,
+                    "The document has {pages} pages, more than the {max_pages} allowed"
+                )
+            }
+            ScanError::PageOutOfRange { index, pages } => {
+                write!(
+                    f,
+                    "Page index {index} is out of range, the document has {pages} pages"
+                )
+            }
+            ScanError::UnexpectedCount { expected, found } => {
+                write!(f, "Expected {expected} barcodes, found {found}")
+            }
+            ScanError::TooManyBarcodes { found, max_results } => {
+                write!(
+                    f,
+                    "Found {found} barcodes, more than the {max_results} allowed"
+                )
+            }
+            ScanError::Image(e) => write!(f, "Failed to read image: {e}"),
+            ScanError::ImageTooLarge {
+                width,
+                height,
+                max_pixels,
+            } => write!(
+                f,
+                "The image is {width}x{height}, more than the {max_pixels} pixels allowed"
+            ),
+            ScanError::Archive(e) => write!(f, "Failed to read archive: {e}"),
+            ScanError::ArchiveTooLarge { max_size } => {
+                write!(f, "The archive extracts to more than {max_size} bytes")
+            }
+            ScanError::DataUri(e) => write!(f, "Invalid data URI: {e}"),
+            ScanError::Encode(e) => write!(f, "Failed to encode barcode: {e}"),
+            ScanError::Video(e) => write!(f, "Failed to extract video frames: {e}"),
+            ScanError::RenderDpis(e) => write!(f, "Invalid render resolutions: {e}"),
+        }
+    }
+}
+
+impl Error for ScanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScanError::Read(e) => Some(e),
+            #[cfg(feature = "pdf")]
+            ScanError::Pdf(e) | ScanError::PdfiumUnavailable(e) => Some(e),
+            ScanError::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl ScanError {
+    /// The error for a file type that cannot be scanned, telling the PDFs apart when their
+    /// support is left out of the build.
+    pub(crate) fn unsupported(mime_type: String) -> Self {
+        if !cfg!(feature = "pdf") && mime_type == "application/pdf" {
+            return ScanError::PdfNotCompiled;
+        }
+
+        ScanError::UnsupportedFileType(mime_type)
+    }
+}
+
+impl From<io::Error> for ScanError {
+    fn from(error: io::Error) -> Self {
+        ScanError::Read(error)
+    }
+}
+
+#[cfg(feature = "pdf")]
+impl From<PdfiumError> for ScanError {
+    fn from(error: PdfiumError) -> Self {
+        ScanError::Pdf(error)
+    }
+}
+
+impl From<ImageError> for ScanError {
+    fn from(error: ImageError) -> Self {
+        ScanError::Image(error)
+    }
+}
+
+/// Where a [`ScanError`] happened, as passed to the [`ErrorHook`].
+#[derive(Debug)]
+pub struct ErrorContext<'a> {
+    /// The failed operation, named after the function called, such as `process_file`.
+    pub operation: &'static str,
+    /// The scanned file, unless scanning from a reader.
+    pub path: Option<&'a Path>,
+    /// The name given to the scanned file in the options.
+    pub file_name: Option<&'a str>,
+}
+
+/// A function called with every error returned by the scans, to report them to services like
+/// Sentry. It runs on the scanning thread, so it should hand the report off quickly.
+#[derive(Clone)]
+pub struct ErrorHook(Arc<HookFn>);
+
+type HookFn = dyn Fn(&ScanError, &ErrorContext) + Send + Sync;
+
+impl ErrorHook {
+    pub fn new(hook: impl Fn(&ScanError, &ErrorContext) + Send + Sync + 'static) -> Self {
+        ErrorHook(Arc::new(hook))
+    }
+
+    pub(crate) fn call(&self, error: &ScanError, context: &ErrorContext) {
+        (self.0)(error, context)
+    }
+}
+
+impl fmt::Debug for ErrorHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorHook")
+    }
+}
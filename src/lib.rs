@@ -1,111 +1,1846 @@
-use image::{DynamicImage, EncodableLayout, ImageFormat};
-use pdfium_render::prelude::*;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use rxing::{BarcodeFormat, DecodeHintType, DecodeHintValue, DecodingHintDictionary};
-use serde::Serialize;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use regex::Regex;
+use rxing::{
+    BarcodeFormat, DecodeHintType, DecodeHintValue, DecodingHintDictionary, RXingResult,
+    RXingResultMetadataType, RXingResultMetadataValue,
+};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    borrow::Cow,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     fs::File,
-    io::Read,
-    path::Path,
-    sync::{Arc, Mutex},
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    iter,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Duration,
 };
 
+mod archive;
+mod checksum;
+mod data_uri;
+#[cfg(feature = "encode")]
+mod encode;
+mod error;
+#[cfg(feature = "office")]
+mod office;
+#[cfg(feature = "pdf")]
+mod pdf;
+#[cfg(feature = "video")]
+mod video;
+
+pub use data_uri::decode_data_uri;
+#[cfg(feature = "encode")]
+pub use encode::encode_barcode;
+pub use error::{ErrorContext, ErrorHook, ScanError};
+#[cfg(feature = "pdf")]
+pub use pdf::pdfium_version;
+
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "camel_case", serde(rename_all = "camelCase"))]
 pub struct BarcodeData {
-    r#type: String,
-    data: String,
+    /// The barcode format.
+    pub r#type: String,
+    /// The decoded text.
+    pub data: String,
+    /// Points locating the barcode in the image, when requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub points: Option<Vec<Point>>,
+    /// Whether the check digit matches, for the formats where it is validated on request. Only
+    /// matching Codabar check characters are reported, since the character is optional.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum_valid: Option<bool>,
+    /// The barcode was read mirrored, as if seen through glass.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub mirrored: bool,
+    /// Some bytes of the barcode could not be decoded in its character set and were replaced with
+    /// U+FFFD, so `data` is not a faithful copy of the payload. Payloads that really contain
+    /// U+FFFD are flagged too.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub lossy: bool,
+    /// Other payloads read in the same area by the retry passes, for damaged codes that do not
+    /// always decode the same. Only collected on request.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<String>,
+    /// How the barcode was found, on request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// A small PNG of the barcode area, as a data URI, to check the read by eye. Only made on
+    /// request, for the barcodes that were located.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    /// Every metadata entry of the decoder, such as `error_correction_level` or
+    /// `symbology_identifier`, as text. Only collected on request.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The detection work behind a barcode, to tune the retry passes.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Provenance {
+    /// The pass that read the barcode.
+    pub pass: DecodePass,
+    /// Number of detection passes run on the image, the candidate passes aside.
+    pub passes: usize,
+}
+
+/// A detection pass of [`decode_image`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodePass {
+    /// The first detection, on the image as given.
+    Normal,
+    /// A detection after blanking out the barcodes already found.
+    Masked,
+    /// A detection after stretching the contrast.
+    Stretched,
+    /// A detection of the image with its gray levels inverted, for light codes on a dark
+    /// background.
+    Inverted,
+    /// A detection of the mirrored image.
+    Mirrored,
+}
+
+/// The outcome of a scan.
+#[derive(Debug, Serialize)]
+pub struct ScanResult {
+    /// Number of barcodes found in the file, regardless of pagination.
+    pub total: usize,
+    /// The barcodes found, in page order, paginated as requested.
+    pub barcodes: Vec<BarcodeData>,
+    /// The scanned pages, or the image for image files, in order.
+    pub pages: Vec<PageInfo>,
+    /// The file held more than `max_results` barcodes, the first ones were kept.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+/// The barcodes found in a single page, or in the image for image files.
+#[derive(Debug, Serialize)]
+pub struct PageResult {
+    #[serde(flatten)]
+    pub info: PageInfo,
+    pub barcodes: Vec<BarcodeData>,
+}
+
+/// How a page, or the image for image files, was scanned.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "camel_case", serde(rename_all = "camelCase"))]
+pub struct PageInfo {
+    /// Width of the scanned image, in pixels.
+    pub width: u32,
+    /// Height of the scanned image, in pixels.
+    pub height: u32,
+    /// Resolution the PDF page was rendered at. Not set for image files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dpi: Option<f32>,
+    /// Position of the frame in the video, in seconds. Only set for video clips.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<f32>,
+    /// The page was found blank and not decoded.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+    /// Decoding the page took longer than the decode timeout and was abandoned, so its barcodes
+    /// are missing.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+    /// Text of the PDF page, when requested. Empty for scans without a text layer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Why the page could not be read, in best effort mode. It has no barcodes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An image to scan, along with the resolution it was rendered at.
+struct Page {
+    image: DynamicImage,
+    dpi: Option<f32>,
+    time: Option<f32>,
+    /// Renders of the same PDF page at the other requested resolutions, whose barcodes are merged
+    /// into the ones of `image`.
+    renders: Vec<DynamicImage>,
+    text: Option<String>,
+    /// The page could not be read, its image is empty.
+    error: Option<String>,
+}
+
+impl From<DynamicImage> for Page {
+    fn from(image: DynamicImage) -> Self {
+        Page {
+            image,
+            dpi: None,
+            time: None,
+            renders: Vec::new(),
+            text: None,
+            error: None,
+        }
+    }
+}
+
+/// In best effort mode, records `error` as a failed page so that the pages read so far are kept,
+/// otherwise fails with it.
+pub(crate) fn keep_going(
+    pages: &mut Vec<Page>,
+    error: ScanError,
+    options: &ScanOptions,
+) -> Result<(), ScanError> {
+    if !options.best_effort {
+        return Err(error);
+    }
+
+    pages.push(Page {
+        error: Some(error.to_string()),
+        ..Page::from(DynamicImage::new_luma8(0, 0))
+    });
+    Ok(())
+}
+
+/// A point in the scanned image.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// How the barcode points are expressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateSpace {
+    /// Pixels of the scanned image, which for PDFs depend on the render resolution.
+    Pixels,
+    /// Fractions of the scanned image width and height, in the `0..=1` range.
+    Normalized,
+}
+
+/// How the payloads of two reads are compared to tell whether they are the same barcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupMode {
+    /// The payloads must be identical.
+    Exact,
+    /// Leading and trailing whitespace and control characters are ignored.
+    Trim,
+    /// Like `Trim`, ignoring the letter case too.
+    CaseFold,
+}
+
+impl DedupMode {
+    /// The part of `data` that must match for two reads to be the same.
+    fn key(self, data: &str) -> String {
+        let trimmed = || data.trim_matches(|c: char| c.is_whitespace() || c.is_control());
+        match self {
+            DedupMode::Exact => data.to_string(),
+            DedupMode::Trim => trimmed().to_string(),
+            DedupMode::CaseFold => trimmed().to_lowercase(),
+        }
+    }
+}
+
+/// What two barcodes must share to be merged: the format, unless merging across formats, and the
+/// payload compared according to `mode`.
+fn dedup_key(
+    barcode: &BarcodeData,
+    mode: DedupMode,
+    options: &ScanOptions,
+) -> (Option<String>, String) {
+    let format = (!options.dedup_across_formats).then(|| barcode.r#type.clone());
+
+    (format, mode.key(&barcode.data))
+}
+
+/// The gray levels the barcodes are searched in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorChannel {
+    /// The perceived brightness of the pixels.
+    #[default]
+    Luma,
+    /// A single channel of the RGB pixels, for codes printed in color: ink shows darkest in the
+    /// channels it absorbs, red ink in the green and blue ones.
+    Red,
+    Green,
+    Blue,
+    /// The brightest of the RGB channels of each pixel, which sets dark codes apart from the
+    /// colored backgrounds they are printed on.
+    Max,
+}
+
+/// Options controlling how a file is scanned.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Maximum number of pages a PDF may have. Larger documents are rejected before rendering.
+    /// Also caps the number of images of a ZIP archive.
+    pub max_pages: usize,
+    /// Maximum number of pixels (width times height) an image may have. Larger images are
+    /// rejected before their pixels are decoded, since a small file can declare huge dimensions.
+    pub max_pixels: u64,
+    /// Maximum number of bytes extracted from a ZIP archive, or an Office document, to guard
+    /// against archives that inflate to huge sizes.
+    pub max_archive_size: u64,
+    /// Decode the pages, and the files of [`process_files`], in parallel on the rayon thread pool.
+    /// Sequential decoding avoids the scheduling overhead on single core machines.
+    pub parallel: bool,
+    /// Render landscape PDF pages rotated by 90 degrees, upright like the portrait ones.
+    pub rotate_landscape: bool,
+    /// Extract the text of the PDF pages from the same loaded document, in the `text` of the
+    /// pages.
+    pub page_text: bool,
+    /// Keep the pages read when another fails to render or to decode, reporting the failed one
+    /// with its `error`, instead of failing the whole file. ZIP archives past their limits keep
+    /// the images read before. Files that cannot be opened or detected still fail.
+    pub best_effort: bool,
+    /// MIME types of the files to accept, such as `image/png` or `application/pdf`. Files of other
+    /// types are rejected before being read any further. Empty accepts every supported type.
+    pub allowed_mime_types: Vec<String>,
+    /// MIME type of the file, used when it cannot be detected from the content, as happens with
+    /// some minimal TIFFs. When scanning a path, defaults to the type guessed from its extension.
+    pub fallback_mime_type: Option<String>,
+    /// MIME type to scan the file as, whatever its content looks like, for the files the
+    /// detection gets wrong. Takes precedence over the detected and the fallback types.
+    pub mime_override: Option<String>,
+    /// After the first detection, blank out the detected barcodes and scan the image again to
+    /// find the codes that were missed or merged with a neighbour. Results are deduplicated by
+    /// payload.
+    pub mask_detected: bool,
+    /// Also scan each half of the images wider than tall, split down the middle, for scans of two
+    /// pages side by side. The barcodes of the halves are merged with those of the whole image.
+    pub split_spreads: bool,
+    /// Include the points locating each barcode, expressed in the given space.
+    pub coordinates: Option<CoordinateSpace>,
+    /// Validate the check digits that the decoder does not enforce (ITF mod 10, the optional
+    /// Codabar mod 16 character), reporting the outcome in `checksum_valid`. A Codabar code without
+    /// a matching check character is left unreported, as it may have been printed without one.
+    /// The EAN and UPC check digits are reported too, though the decoder rejects the codes where
+    /// they mismatch.
+    pub validate_checksums: bool,
+    /// When nothing is found, scan the image again mirrored. QR codes are always read mirrored
+    /// too, this extends it to the other formats.
+    pub try_mirrored: bool,
+    /// When nothing is found, stretch the contrast of the image so that its gray levels span the
+    /// whole range, then scan it again. Rescues washed-out scans.
+    pub stretch_contrast: bool,
+    /// Ignore the barcodes whose located width and height are both below this many pixels of the
+    /// scanned image. Filters out the specks of textured backgrounds read as tiny codes.
+    pub min_size: Option<f32>,
+    /// Only keep the barcodes whose payload matches this pattern, such as `^ACME-` for the codes
+    /// starting with a product prefix.
+    pub payload_filter: Option<Regex>,
+    /// The detection passes to run, in order, until one of them finds a barcode. Contrast
+    /// stretching carries over to the passes that follow it, and `Masked` is left out: masking
+    /// follows the `Normal` pass when `mask_detected` is set. Empty runs `Normal`, then the
+    /// retries enabled by `stretch_contrast` and `try_mirrored`.
+    pub pass_order: Vec<DecodePass>,
+    /// Run every pass of the order, merging the barcodes they read, instead of stopping at the
+    /// first one that finds something.
+    pub run_all_passes: bool,
+    /// Skip decoding the pages, or images, whose gray levels have a standard deviation below this
+    /// value, out of 255. Nearly blank pages are then skipped quickly, and flagged as `skipped`.
+    pub blank_threshold: Option<f32>,
+    /// The gray levels to search the barcodes in.
+    pub channel: ColorChannel,
+    /// Run every retry pass (contrast stretching, inversion, mirroring) even when the first
+    /// detection succeeds, reporting the differing payloads they read in `candidates`.
+    pub collect_candidates: bool,
+    /// Rotate and flip the images as their EXIF orientation tag says, like image viewers do.
+    /// Phone photos are often stored sideways and tagged.
+    pub apply_orientation: bool,
+    /// Attach a thumbnail of each barcode, cropped from the scanned image, in `thumbnail`.
+    pub thumbnails: bool,
+    /// List every metadata entry of the decoder in `metadata`, beyond the curated fields.
+    pub include_metadata: bool,
+    /// Report the pass that read each barcode, and the number of passes run, in `provenance`.
+    pub provenance: bool,
+    /// Merge the barcodes of a page that have the same format and, compared as given, the same
+    /// payload, keeping the first read. Each physical barcode can otherwise be read more than once.
+    pub dedup: Option<DedupMode>,
+    /// Merge the barcodes with the same payload whatever their format, such as a QR code and a
+    /// Code 128 printed side by side with the same value, wherever barcodes are merged: within a
+    /// page with `dedup`, across the renders and halves of a page, across video frames and by
+    /// [`process_files_distinct`].
+    pub dedup_across_formats: bool,
+    /// Formats listed first to last, to order the results by. Barcodes of the same priority, or
+    /// of unlisted formats, which come last, keep their page order.
+    pub format_priority: Vec<BarcodeFormat>,
+    /// Maximum number of barcodes to keep for each listed format, the first ones in result order.
+    /// Applied to the whole file before pagination, by [`process_file`] and [`process_reader`]
+    /// only.
+    pub format_caps: HashMap<BarcodeFormat, usize>,
+    /// Number of barcodes to skip from the start of the results.
+    pub offset: usize,
+    /// Maximum number of barcodes to return.
+    pub limit: Option<usize>,
+    /// Maximum number of barcodes a file may hold, handled as `overflow` says when exceeded.
+    /// Applied after the format caps, by [`process_file`] and [`process_reader`] only.
+    pub max_results: Option<usize>,
+    /// What to do with the files holding more than `max_results` barcodes.
+    pub overflow: Overflow,
+    /// Fail with [`ScanError::UnexpectedCount`] unless the file holds exactly this many barcodes.
+    /// Checked before pagination, by [`process_file`] and [`process_reader`] only.
+    pub expect_count: Option<ExpectedCount>,
+    /// Called with the errors of the scans, to forward them to an error reporting service.
+    pub error_hook: Option<ErrorHook>,
+    /// Name of the scanned file, such as the name of the upload, passed to the error hook.
+    pub file_name: Option<String>,
+    /// Maximum time spent decoding each page, or image. The decoding of a page that takes longer
+    /// is abandoned, leaving the page without barcodes and flagged as `timed_out`, so that one
+    /// pathological image cannot stall a whole batch. The abandoned decoding still runs to
+    /// completion on its own thread, see `max_abandoned_decodes`.
+    pub decode_timeout: Option<Duration>,
+    /// Abandoned decodings of a scan allowed to run at once, 4 by default. Once reached, the next
+    /// pages of the scan are decoded on the calling thread without a timeout, until some of the
+    /// abandoned decodings end, so that one file cannot pile up threads using the CPU. The cap
+    /// is per scan, the scans of other files are not affected.
+    pub max_abandoned_decodes: usize,
+    /// Resolutions to render each PDF page at, in dots per inch, instead of fitting it in 1000
+    /// pixels. The barcodes read at every resolution are merged, each payload of a format once,
+    /// since small 1D codes need a higher resolution than large 2D ones. The pages are reported,
+    /// and the points expressed, at the first resolution. At most 4 resolutions, each up to 1200
+    /// DPI, and every render counts against `max_pixels`.
+    pub render_dpis: Vec<f32>,
+    /// Directory of the intermediate files, such as the frames extracted from video clips.
+    /// Defaults to the system temporary directory.
+    pub temp_dir: Option<PathBuf>,
+    /// Seconds between the frames scanned from video clips. The barcodes read in more than one
+    /// frame are reported once, by [`process_file`] and [`process_reader`].
+    #[cfg(feature = "video")]
+    pub frame_interval: f32,
+    /// Maximum number of frames scanned from a video clip. The rest of the clip is ignored.
+    #[cfg(feature = "video")]
+    pub max_frames: usize,
+}
+
+/// How to handle the files holding more than the maximum number of barcodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// Keep the first barcodes, flagging the result as `truncated`.
+    #[default]
+    Truncate,
+    /// Fail with [`ScanError::TooManyBarcodes`], to reject suspicious files.
+    Error,
+}
+
+/// The number of barcodes a file must hold.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ExpectedCount {
+    pub count: usize,
+    /// Only count the barcodes of this format. Every barcode counts when not set.
+    pub format: Option<BarcodeFormat>,
 }
 
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            max_pages: 500,
+            max_pixels: 50_000_000,
+            max_archive_size: 200_000_000,
+            parallel: true,
+            rotate_landscape: true,
+            page_text: false,
+            best_effort: false,
+            allowed_mime_types: Vec::new(),
+            fallback_mime_type: None,
+            mime_override: None,
+            mask_detected: false,
+            split_spreads: false,
+            coordinates: None,
+            validate_checksums: false,
+            try_mirrored: false,
+            stretch_contrast: false,
+            min_size: None,
+            payload_filter: None,
+            pass_order: Vec::new(),
+            run_all_passes: false,
+            blank_threshold: None,
+            channel: ColorChannel::Luma,
+            collect_candidates: false,
+            apply_orientation: false,
+            provenance: false,
+            include_metadata: false,
+            thumbnails: false,
+            dedup: None,
+            dedup_across_formats: false,
+            format_priority: Vec::new(),
+            format_caps: HashMap::new(),
+            offset: 0,
+            limit: None,
+            max_results: None,
+            overflow: Overflow::Truncate,
+            expect_count: None,
+            error_hook: None,
+            file_name: None,
+            decode_timeout: None,
+            max_abandoned_decodes: 4,
+            render_dpis: Vec::new(),
+            temp_dir: None,
+            #[cfg(feature = "video")]
+            frame_interval: 1.0,
+            #[cfg(feature = "video")]
+            max_frames: 60,
+        }
+    }
+}
+
+/// MIME types of the supported image files.
+const IMAGE_MIME_TYPES: [&str; 6] = [
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/tiff",
+    "image/bmp",
+];
+
+/// Number of leading bytes inspected to detect the file type.
+const SNIFF_LEN: u64 = 8192;
+
+/// Maximum number of additional detection passes run when masking detected barcodes.
+const MASK_PASSES: usize = 4;
+
+/// Largest side of the barcode thumbnails, in pixels.
+const THUMBNAIL_SIZE: u32 = 128;
+
+/// Fraction of the darkest and of the lightest pixels ignored when stretching the contrast, so
+/// that a few specks do not hold the range back.
+const STRETCH_CLIP: f32 = 0.005;
+
 /// Process the file and extract barcodes.
+///
+/// The work runs on the blocking thread pool of the tokio runtime, leaving the async workers free.
 pub async fn process_file(
     path: &Path,
     hints: Option<DecodingHintDictionary>,
-) -> Result<Vec<BarcodeData>, String> {
-    let images = get_images(&path).map_err(|e| e.to_string())?;
-    let barcode_list = Arc::new(Mutex::new(Vec::new()));
+    options: &ScanOptions,
+) -> Result<ScanResult, ScanError> {
+    let path = path.to_path_buf();
+    let options = options.clone();
+
+    run_blocking(move || process_file_blocking(&path, hints, &options)).await
+}
+
+/// Process the file and extract barcodes, blocking the current thread.
+pub fn process_file_blocking(
+    path: &Path,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<ScanResult, ScanError> {
+    report(options, "process_file", Some(path), || {
+        let file = File::open(path)?;
+
+        scan_reader(file, hints, &with_extension_fallback(path, options))
+    })
+}
+
+/// Process the file held in `bytes` and extract barcodes.
+///
+/// Like [`process_file`], the work runs on the blocking thread pool.
+pub async fn process_bytes(
+    bytes: Vec<u8>,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<ScanResult, ScanError> {
+    let options = options.clone();
 
-    images.par_iter().for_each(|image| {
-        let width = image.width();
-        let height = image.height();
-        let luma_image: Vec<u8> = image.clone().into_luma8().as_bytes().into();
+    run_blocking(move || process_bytes_blocking(bytes, hints, &options)).await
+}
+
+/// Like [`process_bytes`], blocking the current thread.
+pub fn process_bytes_blocking(
+    bytes: Vec<u8>,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<ScanResult, ScanError> {
+    report(options, "process_bytes", None, || {
+        scan_reader(Cursor::new(bytes), hints, options)
+    })
+}
+
+/// Process several files and extract their barcodes, returning the results in the same order.
+///
+/// Files are scanned in parallel, on the blocking thread pool like [`process_file`].
+pub async fn process_files(
+    paths: Vec<PathBuf>,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Vec<Result<ScanResult, ScanError>> {
+    let options = options.clone();
+
+    run_blocking(move || process_files_blocking(&paths, hints, &options)).await
+}
+
+/// Like [`process_files`], blocking the current thread.
+pub fn process_files_blocking(
+    paths: &[PathBuf],
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Vec<Result<ScanResult, ScanError>> {
+    let scan = |path: &PathBuf| process_file_blocking(path, hints.clone(), options);
+    if options.parallel {
+        paths.par_iter().map(scan).collect()
+    } else {
+        paths.iter().map(scan).collect()
+    }
+}
+
+/// Process several files and return each distinct barcode once, with the files it was found in.
+///
+/// Barcodes are told apart by format, unless `dedup_across_formats` is set, and by payload,
+/// compared according to the `dedup` mode, or exactly if none is set. They come in the order they
+/// were first seen, and keep the details of the first read. Files that fail to scan are left out,
+/// their errors still reach the error hook.
+pub async fn process_files_distinct(
+    paths: Vec<PathBuf>,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Vec<(BarcodeData, Vec<PathBuf>)> {
+    let options = options.clone();
+
+    run_blocking(move || process_files_distinct_blocking(&paths, hints, &options)).await
+}
 
-        let results = match &mut hints.clone() {
-            Some(hints) => {
-                rxing::helpers::detect_multiple_in_luma_with_hints(luma_image, width, height, hints)
+/// Like [`process_files_distinct`], blocking the current thread.
+pub fn process_files_distinct_blocking(
+    paths: &[PathBuf],
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Vec<(BarcodeData, Vec<PathBuf>)> {
+    let mode = options.dedup.unwrap_or(DedupMode::Exact);
+    let mut distinct: Vec<(BarcodeData, Vec<PathBuf>)> = Vec::new();
+    let mut positions: HashMap<(Option<String>, String), usize> = HashMap::new();
+
+    let results = process_files_blocking(paths, hints, options);
+    for (path, result) in paths.iter().zip(results) {
+        let Ok(result) = result else {
+            continue;
+        };
+        for barcode in result.barcodes {
+            let key = dedup_key(&barcode, mode, options);
+            match positions.get(&key) {
+                Some(&position) => {
+                    let files = &mut distinct[position].1;
+                    if files.last() != Some(path) {
+                        files.push(path.clone());
+                    }
+                }
+                None => {
+                    positions.insert(key, distinct.len());
+                    distinct.push((barcode, vec![path.clone()]));
+                }
             }
-            None => rxing::helpers::detect_multiple_in_luma(luma_image, width, height),
+        }
+    }
+
+    distinct
+}
+
+/// Process the data of `reader` and extract barcodes.
+///
+/// Only the header is buffered to detect the file type, the decoders then read the data as they
+/// need it. Like [`process_file`], the work runs on the blocking thread pool.
+pub async fn process_reader<R: Read + Seek + Send + 'static>(
+    reader: R,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<ScanResult, ScanError> {
+    let options = options.clone();
+
+    run_blocking(move || process_reader_blocking(reader, hints, &options)).await
+}
+
+/// Process the data of `reader` and extract barcodes, blocking the current thread.
+pub fn process_reader_blocking(
+    reader: impl Read + Seek,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<ScanResult, ScanError> {
+    report(options, "process_reader", None, || {
+        scan_reader(reader, hints, options)
+    })
+}
+
+/// Decodes the barcodes of `reader`, then orders, checks and paginates them as requested.
+fn scan_reader(
+    reader: impl Read + Seek,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<ScanResult, ScanError> {
+    let scanned = scan_pages(reader, &hints, options)?;
+    let pages: Vec<PageInfo> = scanned.iter().map(|page| page.info.clone()).collect();
+    let mut barcodes: Vec<BarcodeData> =
+        scanned.into_iter().flat_map(|page| page.barcodes).collect();
+
+    // the frames of a clip overlap, so the same physical barcode shows up in many of them
+    if pages.iter().any(|page| page.time.is_some()) {
+        let mode = options.dedup.unwrap_or(DedupMode::Exact);
+        let mut seen = HashSet::new();
+        barcodes.retain(|barcode| seen.insert(dedup_key(barcode, mode, options)));
+    }
+
+    if !options.format_priority.is_empty() {
+        let priority: Vec<String> = options
+            .format_priority
+            .iter()
+            .map(|format| format.to_string())
+            .collect();
+        barcodes.sort_by_key(|barcode| {
+            priority
+                .iter()
+                .position(|format| *format == barcode.r#type)
+                .unwrap_or(priority.len())
+        });
+    }
+
+    if !options.format_caps.is_empty() {
+        let mut remaining: HashMap<String, usize> = options
+            .format_caps
+            .iter()
+            .map(|(format, cap)| (format.to_string(), *cap))
+            .collect();
+        barcodes.retain(|barcode| match remaining.get_mut(&barcode.r#type) {
+            Some(0) => false,
+            Some(remaining) => {
+                *remaining -= 1;
+                true
+            }
+            None => true,
+        });
+    }
+
+    let mut truncated = false;
+    if let Some(max_results) = options.max_results {
+        if barcodes.len() > max_results {
+            match options.overflow {
+                Overflow::Truncate => {
+                    barcodes.truncate(max_results);
+                    truncated = true;
+                }
+                Overflow::Error => {
+                    return Err(ScanError::TooManyBarcodes {
+                        found: barcodes.len(),
+                        max_results,
+                    })
+                }
+            }
+        }
+    }
+
+    if let Some(expected) = options.expect_count {
+        let format = expected.format.map(|format| format.to_string());
+        let found = barcodes
+            .iter()
+            .filter(|barcode| {
+                format
+                    .as_ref()
+                    .is_none_or(|format| *format == barcode.r#type)
+            })
+            .count();
+        if found != expected.count {
+            return Err(ScanError::UnexpectedCount {
+                expected: expected.count,
+                found,
+            });
+        }
+    }
+
+    let total = barcodes.len();
+    let barcodes = barcodes
+        .into_iter()
+        .skip(options.offset)
+        .take(options.limit.unwrap_or(usize::MAX))
+        .collect();
+
+    Ok(ScanResult {
+        total,
+        barcodes,
+        pages,
+        truncated,
+    })
+}
+
+/// Process the file and extract barcodes, keeping the results of each page separate.
+///
+/// Pagination options do not apply, every barcode of every page is returned.
+pub async fn process_file_by_page(
+    path: &Path,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<Vec<PageResult>, ScanError> {
+    let path = path.to_path_buf();
+    let options = options.clone();
+
+    run_blocking(move || process_file_by_page_blocking(&path, hints, &options)).await
+}
+
+/// Like [`process_file_by_page`], blocking the current thread.
+pub fn process_file_by_page_blocking(
+    path: &Path,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<Vec<PageResult>, ScanError> {
+    report(options, "process_file_by_page", Some(path), || {
+        let file = File::open(path)?;
+
+        scan_pages(file, &hints, &with_extension_fallback(path, options))
+    })
+}
+
+/// Process the file and extract barcodes, returning each scanned page, or image, along with its
+/// barcodes. The coordinates are those of the returned images, ready for drawing overlays.
+///
+/// Pagination options do not apply, every barcode of every page is returned.
+pub async fn process_file_with_images(
+    path: &Path,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<Vec<(DynamicImage, PageResult)>, ScanError> {
+    let path = path.to_path_buf();
+    let options = options.clone();
+
+    run_blocking(move || process_file_with_images_blocking(&path, hints, &options)).await
+}
+
+/// Like [`process_file_with_images`], blocking the current thread.
+pub fn process_file_with_images_blocking(
+    path: &Path,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<Vec<(DynamicImage, PageResult)>, ScanError> {
+    report(options, "process_file_with_images", Some(path), || {
+        let pages = get_images(path, options)?;
+
+        let abandoned = Arc::default();
+        let scan = |page: Page| {
+            let result = scan_page(&page, &hints, options, &abandoned);
+            (page.image, result)
         };
+        Ok(if options.parallel {
+            pages.into_par_iter().map(scan).collect()
+        } else {
+            pages.into_iter().map(scan).collect()
+        })
+    })
+}
+
+/// Render and decode a single page of a PDF, `page_index` starting from 0.
+///
+/// Only the requested page is rendered, which makes scanning one page of a large document cheap.
+#[cfg(feature = "pdf")]
+pub async fn scan_pdf_page(
+    path: &Path,
+    page_index: usize,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<PageResult, ScanError> {
+    let path = path.to_path_buf();
+    let options = options.clone();
+
+    run_blocking(move || scan_pdf_page_blocking(&path, page_index, hints, &options)).await
+}
+
+/// Like [`scan_pdf_page`], blocking the current thread.
+#[cfg(feature = "pdf")]
+pub fn scan_pdf_page_blocking(
+    path: &Path,
+    page_index: usize,
+    hints: Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<PageResult, ScanError> {
+    report(options, "scan_pdf_page", Some(path), || {
+        let mut file = File::open(path)?;
+        let mime_type = sniff_file_type(&mut file, &with_extension_fallback(path, options))?;
+        check_allowed(&mime_type, options)?;
+        if mime_type != "application/pdf" {
+            return Err(ScanError::UnsupportedFileType(mime_type));
+        }
+
+        let page = pdf::extract_page(file, page_index, options)?;
+
+        Ok(scan_page(&page, &hints, options, &Arc::default()))
+    })
+}
+
+/// Decodes the barcodes of every page (or image) read from `reader`.
+fn scan_pages(
+    reader: impl Read + Seek,
+    hints: &Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Result<Vec<PageResult>, ScanError> {
+    let pages = read_images(reader, options)?;
 
-        if let Ok(results) = results {
-            for result in results {
-                let mut list = barcode_list.lock().expect("acquired Mutex");
-                list.push(BarcodeData {
-                    r#type: result.getBarcodeFormat().to_string(),
-                    data: result.getText().to_string(),
-                });
+    let abandoned = Arc::default();
+    let scan = |page: &Page| scan_page(page, hints, options, &abandoned);
+    // collecting keeps the page order, so that the results are stable across calls
+    Ok(if options.parallel {
+        pages.par_iter().map(scan).collect()
+    } else {
+        pages.iter().map(scan).collect()
+    })
+}
+
+/// Decodes the barcodes of a single page, `abandoned` counting the decodings of the scan that
+/// timed out but still run.
+fn scan_page(
+    page: &Page,
+    hints: &Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+    abandoned: &Arc<AtomicUsize>,
+) -> PageResult {
+    let skipped = page.error.is_none()
+        && options
+            .blank_threshold
+            .is_some_and(|threshold| is_blank(&page.image, threshold));
+
+    let decode = |image: &DynamicImage| match options.decode_timeout {
+        None => Some(decode_image(image, hints, options)),
+        Some(timeout) => decode_image_with_timeout(image, hints, options, timeout, abandoned),
+    };
+
+    // the renders and halves of a page read the same barcodes again, whatever the dedup option
+    let mode = options.dedup.unwrap_or(DedupMode::Exact);
+    let mut timed_out = false;
+    let mut barcodes = Vec::new();
+    for image in iter::once(&page.image).chain(&page.renders) {
+        if skipped || page.error.is_some() {
+            break;
+        }
+        // the points of the other renders are moved to the pixels of the reported one
+        let scale = page.image.width() as f32 / image.width() as f32;
+
+        for (offset, view) in spread_views(image, options.split_spreads) {
+            let Some(found) = decode(&view) else {
+                timed_out = true;
+                continue;
+            };
+
+            // only the reads of the previous views count, the repeats within one are kept
+            let previous = barcodes.len();
+            for mut barcode in found {
+                let key = dedup_key(&barcode, mode, options);
+                let seen = barcodes[..previous]
+                    .iter()
+                    .any(|other| dedup_key(other, mode, options) == key);
+                if seen {
+                    continue;
+                }
+                for point in barcode.points.iter_mut().flatten() {
+                    match options.coordinates {
+                        Some(CoordinateSpace::Pixels) => {
+                            point.x = (point.x + offset as f32) * scale;
+                            point.y *= scale;
+                        }
+                        // a half spans part of the width only, the height is the same
+                        Some(CoordinateSpace::Normalized) => {
+                            point.x = (point.x * view.width() as f32 + offset as f32)
+                                / image.width() as f32;
+                        }
+                        None => {}
+                    }
+                }
+                barcodes.push(barcode);
             }
         }
+    }
+
+    PageResult {
+        info: PageInfo {
+            width: page.image.width(),
+            height: page.image.height(),
+            dpi: page.dpi,
+            time: page.time,
+            skipped,
+            timed_out,
+            text: page.text.clone(),
+            error: page.error.clone(),
+        },
+        barcodes,
+    }
+}
+
+/// The parts of `image` to decode, with the offset of their left edge: the whole image, then its
+/// halves when splitting a spread that is wider than tall.
+fn spread_views(image: &DynamicImage, split: bool) -> Vec<(u32, Cow<'_, DynamicImage>)> {
+    let mut views = vec![(0, Cow::Borrowed(image))];
+    let (width, height) = (image.width(), image.height());
+    if split && width > height {
+        let half = width / 2;
+        views.push((0, Cow::Owned(image.crop_imm(0, 0, half, height))));
+        views.push((
+            half,
+            Cow::Owned(image.crop_imm(half, 0, width - half, height)),
+        ));
+    }
+
+    views
+}
+
+/// Decodes the image on a thread of its own, giving up on it after `timeout`.
+///
+/// Once `options.max_abandoned_decodes` of the decodings counted by `abandoned` still run, the
+/// image is decoded on the calling thread instead, without a timeout.
+fn decode_image_with_timeout(
+    image: &DynamicImage,
+    hints: &Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+    timeout: Duration,
+    abandoned: &Arc<AtomicUsize>,
+) -> Option<Vec<BarcodeData>> {
+    // the abandoned decodings still use the CPU, starting more would only pile them up
+    if abandoned.load(Ordering::Acquire) >= options.max_abandoned_decodes {
+        return Some(decode_image(image, hints, options));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let (image, hints, options) = (image.clone(), hints.clone(), options.clone());
+    let state = Arc::new(AtomicU8::new(DECODING));
+    let guard = DecodeGuard {
+        state: Arc::clone(&state),
+        abandoned: Arc::clone(abandoned),
+    };
+    let handle = thread::spawn(move || {
+        let _guard = guard;
+        // nobody listens anymore once the timeout has passed
+        let _ = sender.send(decode_image(&image, &hints, &options));
     });
 
-    Ok(Arc::into_inner(barcode_list)
-        .expect("valid Arc")
-        .into_inner()
-        .expect("valid Mutex"))
+    match receiver.recv_timeout(timeout) {
+        Ok(barcodes) => Some(barcodes),
+        Err(RecvTimeoutError::Timeout) => {
+            // counted first, so that a decoding ending meanwhile cannot uncount it before
+            abandoned.fetch_add(1, Ordering::AcqRel);
+            let swapped = state
+                .compare_exchange(DECODING, ABANDONED, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok();
+            if !swapped {
+                abandoned.fetch_sub(1, Ordering::AcqRel);
+            }
+            None
+        }
+        // the sender is only dropped without sending when the decoding panics
+        Err(RecvTimeoutError::Disconnected) => match handle.join() {
+            Err(payload) => std::panic::resume_unwind(payload),
+            Ok(()) => unreachable!("the decoding thread ended without a result"),
+        },
+    }
+}
+
+/// States of a decoding thread, shared with the thread waiting for it.
+const DECODING: u8 = 0;
+const DONE: u8 = 1;
+const ABANDONED: u8 = 2;
+
+/// Marks the decoding done when its thread ends, even by panicking, uncounting it if it was
+/// abandoned.
+struct DecodeGuard {
+    state: Arc<AtomicU8>,
+    /// The abandoned decodings of the scan.
+    abandoned: Arc<AtomicUsize>,
 }
 
-/// Creates barcode detection hints from the given formats.
-pub fn create_hints(
-    formats: Option<Vec<BarcodeFormat>>,
-) -> Option<HashMap<DecodeHintType, DecodeHintValue>> {
-    formats.map(|formats| {
-        HashMap::from([(
-            DecodeHintType::POSSIBLE_FORMATS,
-            DecodeHintValue::PossibleFormats(HashSet::from_iter(formats)),
-        )])
+impl Drop for DecodeGuard {
+    fn drop(&mut self) {
+        if self.state.swap(DONE, Ordering::AcqRel) == ABANDONED {
+            self.abandoned.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// Whether the standard deviation of the gray levels of the image is below `threshold`.
+fn is_blank(image: &DynamicImage, threshold: f32) -> bool {
+    let luma_image = image.to_luma8();
+    let pixels = luma_image.as_raw();
+    if pixels.is_empty() {
+        return true;
+    }
+
+    let count = pixels.len() as f64;
+    let mean = pixels.iter().map(|&value| f64::from(value)).sum::<f64>() / count;
+    let variance = pixels
+        .iter()
+        .map(|&value| (f64::from(value) - mean).powi(2))
+        .sum::<f64>()
+        / count;
+
+    variance.sqrt() < f64::from(threshold)
+}
+
+/// Runs `scan`, passing its error to the error hook of the options, if any.
+fn report<T>(
+    options: &ScanOptions,
+    operation: &'static str,
+    path: Option<&Path>,
+    scan: impl FnOnce() -> Result<T, ScanError>,
+) -> Result<T, ScanError> {
+    let result = scan();
+    if let (Err(error), Some(hook)) = (&result, &options.error_hook) {
+        hook.call(
+            error,
+            &ErrorContext {
+                operation,
+                path,
+                file_name: options.file_name.as_deref(),
+            },
+        );
+    }
+
+    result
+}
+
+/// Runs `f` on the blocking thread pool of the tokio runtime, forwarding its panics.
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Decodes the barcodes found in a single image, as the scans do for each page.
+///
+/// The options about the image itself, such as masking or mirroring, apply. The ones about files
+/// and result lists, such as pagination, do not.
+pub fn decode_image(
+    image: &DynamicImage,
+    hints: &Option<DecodingHintDictionary>,
+    options: &ScanOptions,
+) -> Vec<BarcodeData> {
+    let width = image.width();
+    let height = image.height();
+    // there is nothing to find in an empty image, and the passes expect rows of pixels
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    let mut luma_image = gray_levels(image, options.channel);
+    let original = options.collect_candidates.then(|| luma_image.clone());
+
+    // the Codabar checksum covers the start and stop characters, which are stripped by default
+    let mut strip_codabar_start_end = false;
+    let mut hints = hints.clone();
+    if options.validate_checksums {
+        let hints = hints.get_or_insert_with(HashMap::new);
+        if let Entry::Vacant(entry) = hints.entry(DecodeHintType::RETURN_CODABAR_START_END) {
+            entry.insert(DecodeHintValue::ReturnCodabarStartEnd(true));
+            strip_codabar_start_end = true;
+        }
+    }
+    let hints = &hints;
+
+    // drops the reads too small to be real barcodes before the retry passes look at the results
+    let tag = |pass, results: Vec<RXingResult>| -> Vec<(DecodePass, RXingResult)> {
+        results
+            .into_iter()
+            .filter(|result| {
+                options
+                    .min_size
+                    .is_none_or(|min_size| size(result) >= min_size)
+            })
+            .map(|result| (pass, result))
+            .collect()
+    };
+
+    let order: Vec<DecodePass> = if options.pass_order.is_empty() {
+        [
+            Some(DecodePass::Normal),
+            options.stretch_contrast.then_some(DecodePass::Stretched),
+            options.try_mirrored.then_some(DecodePass::Mirrored),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    } else {
+        options.pass_order.clone()
+    };
+
+    let mut passes = 0;
+    let mut results = Vec::new();
+    for pass in order {
+        if !results.is_empty() && !options.run_all_passes {
+            break;
+        }
+
+        let found = match pass {
+            DecodePass::Normal => detect(luma_image.clone(), width, height, hints),
+            DecodePass::Stretched => {
+                // the stretched levels carry over to the following passes
+                if !stretch_contrast(&mut luma_image) {
+                    continue;
+                }
+                detect(luma_image.clone(), width, height, hints)
+            }
+            DecodePass::Inverted => {
+                let inverted = luma_image.iter().map(|value| u8::MAX - value).collect();
+                detect(inverted, width, height, hints)
+            }
+            DecodePass::Mirrored => detect_mirrored(luma_image.clone(), width, height, hints),
+            DecodePass::Masked => continue,
+        };
+        passes += 1;
+        let found = tag(pass, found);
+
+        if pass == DecodePass::Normal && options.mask_detected && !found.is_empty() {
+            let mut masked = found;
+            let mut found: Vec<RXingResult> =
+                masked.iter().map(|(_, result)| result.clone()).collect();
+            for _ in 0..MASK_PASSES {
+                for result in &found {
+                    mask_result(&mut luma_image, width, height, result);
+                }
+
+                found = detect(luma_image.clone(), width, height, hints);
+                passes += 1;
+                if found.is_empty() {
+                    break;
+                }
+                masked.extend(tag(DecodePass::Masked, found.clone()));
+            }
+
+            let mut payloads = HashSet::new();
+            masked.retain(|(_, result)| payloads.insert(result.getText().to_string()));
+            results.extend(masked);
+        } else {
+            results.extend(found);
+        }
+    }
+
+    if options.run_all_passes {
+        let mut seen = HashSet::new();
+        results.retain(|(_, result)| {
+            seen.insert((*result.getBarcodeFormat(), result.getText().to_string()))
+        });
+    }
+
+    let alternatives = original
+        .map(|luma_image| detect_alternatives(luma_image, width, height, hints))
+        .unwrap_or_default();
+
+    let text = |result: &RXingResult| {
+        let data = result.getText();
+        if strip_codabar_start_end && *result.getBarcodeFormat() == BarcodeFormat::CODABAR {
+            data.get(1..data.len().saturating_sub(1))
+                .unwrap_or(data)
+                .to_string()
+        } else {
+            data.to_string()
+        }
+    };
+
+    let mut barcodes: Vec<BarcodeData> = results
+        .into_iter()
+        .map(|(pass, result)| {
+            let format = result.getBarcodeFormat();
+            let checksum_valid = options
+                .validate_checksums
+                .then(|| checksum::checksum_valid(format, result.getText()))
+                .flatten();
+            let data = text(&result);
+
+            let mut candidates = Vec::new();
+            for alternative in &alternatives {
+                let candidate = text(alternative);
+                if alternative.getBarcodeFormat() == format
+                    && candidate != data
+                    && !candidates.contains(&candidate)
+                    && same_region(&result, alternative)
+                {
+                    candidates.push(candidate);
+                }
+            }
+
+            BarcodeData {
+                r#type: format.to_string(),
+                lossy: data.contains(char::REPLACEMENT_CHARACTER),
+                data,
+                points: options
+                    .coordinates
+                    .map(|space| result_points(&result, space, width, height)),
+                checksum_valid,
+                mirrored: matches!(
+                    result
+                        .getRXingResultMetadata()
+                        .get(&RXingResultMetadataType::IS_MIRRORED),
+                    Some(RXingResultMetadataValue::IsMirrored(true))
+                ),
+                candidates,
+                thumbnail: options
+                    .thumbnails
+                    .then(|| thumbnail(image, &result))
+                    .flatten(),
+                metadata: if options.include_metadata {
+                    metadata_entries(&result)
+                } else {
+                    BTreeMap::new()
+                },
+                provenance: options.provenance.then_some(Provenance { pass, passes }),
+            }
+        })
+        .collect();
+
+    if let Some(filter) = &options.payload_filter {
+        barcodes.retain(|barcode| filter.is_match(&barcode.data));
+    }
+
+    if let Some(mode) = options.dedup {
+        let mut seen = HashSet::new();
+        barcodes.retain(|barcode| seen.insert(dedup_key(barcode, mode, options)));
+    }
+
+    barcodes
+}
+
+/// Converts the image to the gray levels of `channel`, one byte per pixel.
+fn gray_levels(image: &DynamicImage, channel: ColorChannel) -> Vec<u8> {
+    let index = match channel {
+        ColorChannel::Luma => return image.to_luma8().into_raw(),
+        ColorChannel::Red => 0,
+        ColorChannel::Green => 1,
+        ColorChannel::Blue => 2,
+        ColorChannel::Max => {
+            return image
+                .to_rgb8()
+                .pixels()
+                .map(|pixel| pixel.0.into_iter().max().unwrap_or_default())
+                .collect()
+        }
+    };
+
+    image
+        .to_rgb8()
+        .pixels()
+        .map(|pixel| pixel.0[index])
+        .collect()
+}
+
+/// Runs barcode detection on the mirrored luma buffer, mapping the results back to the buffer
+/// as given.
+fn detect_mirrored(
+    mut luma_image: Vec<u8>,
+    width: u32,
+    height: u32,
+    hints: &Option<DecodingHintDictionary>,
+) -> Vec<RXingResult> {
+    flip_horizontally(&mut luma_image, width);
+
+    let mut results = detect(luma_image, width, height, hints);
+    for result in &mut results {
+        for point in result.getPointsMut() {
+            point.x = width as f32 - point.x;
+        }
+        result.putMetadata(
+            RXingResultMetadataType::IS_MIRRORED,
+            RXingResultMetadataValue::IsMirrored(true),
+        );
+    }
+    results
+}
+
+/// Runs every retry pass on the luma buffer, whether or not the first detection found anything,
+/// to collect the payloads each of them reads.
+fn detect_alternatives(
+    luma_image: Vec<u8>,
+    width: u32,
+    height: u32,
+    hints: &Option<DecodingHintDictionary>,
+) -> Vec<RXingResult> {
+    let mut results = Vec::new();
+
+    let mut stretched = luma_image.clone();
+    if stretch_contrast(&mut stretched) {
+        results.extend(detect(stretched, width, height, hints));
+    }
+    let inverted = luma_image.iter().map(|value| u8::MAX - value).collect();
+    results.extend(detect(inverted, width, height, hints));
+    results.extend(detect_mirrored(luma_image, width, height, hints));
+
+    results
+}
+
+/// Whether `other` was read in the area of `result`, padded as when masking it.
+fn same_region(result: &RXingResult, other: &RXingResult) -> bool {
+    let (
+        Some((min_x, min_y, max_x, max_y)),
+        Some((other_min_x, other_min_y, other_max_x, other_max_y)),
+    ) = (bounds(result), bounds(other))
+    else {
+        return false;
+    };
+
+    let padding = (max_x - min_x).max(max_y - min_y) * 0.25 + 8.0;
+    let center_x = (other_min_x + other_max_x) / 2.0;
+    let center_y = (other_min_y + other_max_y) / 2.0;
+
+    (min_x - padding..=max_x + padding).contains(&center_x)
+        && (min_y - padding..=max_y + padding).contains(&center_y)
+}
+
+/// The larger side of the rectangle holding the points of a result, in pixels. Results without
+/// points are infinitely large, so that they are never filtered out.
+fn size(result: &RXingResult) -> f32 {
+    bounds(result).map_or(f32::INFINITY, |(min_x, min_y, max_x, max_y)| {
+        (max_x - min_x).max(max_y - min_y)
     })
 }
 
+/// The smallest rectangle holding the points of a result, as `(min_x, min_y, max_x, max_y)`.
+fn bounds(result: &RXingResult) -> Option<(f32, f32, f32, f32)> {
+    let points = result.getPoints();
+    if points.is_empty() {
+        return None;
+    }
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    for point in points {
+        min_x = min_x.min(point.x);
+        min_y = min_y.min(point.y);
+        max_x = max_x.max(point.x);
+        max_y = max_y.max(point.y);
+    }
+    Some((min_x, min_y, max_x, max_y))
+}
+
+/// Remaps the gray levels of the luma buffer to the whole `0..=255` range. Returns whether the
+/// buffer changed.
+fn stretch_contrast(luma_image: &mut [u8]) -> bool {
+    let mut histogram = [0usize; 256];
+    for &value in luma_image.iter() {
+        histogram[value as usize] += 1;
+    }
+
+    // first level past the clipped pixels, counting from the given end of the histogram
+    let clipped = (luma_image.len() as f32 * STRETCH_CLIP) as usize;
+    let level = |levels: &mut dyn Iterator<Item = usize>| {
+        let mut count = 0;
+        for level in levels {
+            count += histogram[level];
+            if count > clipped {
+                return level;
+            }
+        }
+        0
+    };
+    let low = level(&mut (0..256));
+    let high = level(&mut (0..256).rev());
+    if high <= low || (low == 0 && high == 255) {
+        return false;
+    }
+
+    let range = (high - low) as u32;
+    for value in luma_image.iter_mut() {
+        let clamped = (*value as usize).clamp(low, high) - low;
+        *value = (clamped as u32 * 255 / range) as u8;
+    }
+
+    true
+}
+
+/// Mirrors the luma buffer around its vertical axis.
+fn flip_horizontally(luma_image: &mut [u8], width: u32) {
+    for row in luma_image.chunks_exact_mut(width as usize) {
+        row.reverse();
+    }
+}
+
+/// The metadata of a detection result, keyed by the snake case names of the entries. Byte
+/// segments are encoded in base64, separated by spaces.
+fn metadata_entries(result: &RXingResult) -> BTreeMap<String, String> {
+    result
+        .getRXingResultMetadata()
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                RXingResultMetadataValue::OTHER(text)
+                | RXingResultMetadataValue::ErrorCorrectionLevel(text)
+                | RXingResultMetadataValue::SuggestedPrice(text)
+                | RXingResultMetadataValue::PossibleCountry(text)
+                | RXingResultMetadataValue::UpcEanExtension(text)
+                | RXingResultMetadataValue::SymbologyIdentifier(text)
+                | RXingResultMetadataValue::ContentType(text) => text.clone(),
+                RXingResultMetadataValue::Orientation(number)
+                | RXingResultMetadataValue::IssueNumber(number)
+                | RXingResultMetadataValue::StructuredAppendSequence(number)
+                | RXingResultMetadataValue::StructuredAppendParity(number) => number.to_string(),
+                RXingResultMetadataValue::IsMirrored(flag)
+                | RXingResultMetadataValue::IsInverted(flag)
+                | RXingResultMetadataValue::FilteredClosed(flag) => flag.to_string(),
+                RXingResultMetadataValue::ByteSegments(segments) => segments
+                    .iter()
+                    .map(|segment| STANDARD.encode(segment))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                RXingResultMetadataValue::FilteredResolution((width, height)) => {
+                    format!("{width}x{height}")
+                }
+                RXingResultMetadataValue::Pdf417ExtraMetadata(extra) => format!("{extra:?}"),
+            };
+            (format!("{key:?}").to_lowercase(), value)
+        })
+        .collect()
+}
+
+/// Converts the points of a detection result to the requested coordinate space.
+fn result_points(
+    result: &RXingResult,
+    space: CoordinateSpace,
+    width: u32,
+    height: u32,
+) -> Vec<Point> {
+    let (scale_x, scale_y) = match space {
+        CoordinateSpace::Pixels => (1.0, 1.0),
+        CoordinateSpace::Normalized => (width as f32, height as f32),
+    };
+
+    result
+        .getPoints()
+        .iter()
+        .map(|point| Point {
+            x: point.x / scale_x,
+            y: point.y / scale_y,
+        })
+        .collect()
+}
+
+/// Runs barcode detection on a luma buffer. A failed detection yields no results.
+fn detect(
+    luma_image: Vec<u8>,
+    width: u32,
+    height: u32,
+    hints: &Option<DecodingHintDictionary>,
+) -> Vec<RXingResult> {
+    let mut hints = hints.clone();
+
+    // rxing only reaches its rMQR decoder through the Micro QR one, so asking for rMQR alone
+    // finds nothing: look for Micro QR as well, then drop the results nobody asked for
+    let mut drop_micro_qr = false;
+    if let Some(DecodeHintValue::PossibleFormats(formats)) = hints
+        .as_mut()
+        .and_then(|hints| hints.get_mut(&DecodeHintType::POSSIBLE_FORMATS))
+    {
+        if formats.contains(&BarcodeFormat::RECTANGULAR_MICRO_QR_CODE) {
+            drop_micro_qr = formats.insert(BarcodeFormat::MICRO_QR_CODE);
+        }
+    }
+
+    let results = match &mut hints {
+        Some(hints) => {
+            rxing::helpers::detect_multiple_in_luma_with_hints(luma_image, width, height, hints)
+        }
+        None => rxing::helpers::detect_multiple_in_luma(luma_image, width, height),
+    };
+
+    let mut results = results.unwrap_or_default();
+    if drop_micro_qr {
+        results.retain(|result| *result.getBarcodeFormat() != BarcodeFormat::MICRO_QR_CODE);
+    }
+    results
+}
+
+/// The area spanned by the points of a result, padded to cover the whole symbol and clipped to
+/// the image, as `(left, top, right, bottom)`.
+fn padded_area(result: &RXingResult, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+    let (min_x, min_y, max_x, max_y) = bounds(result)?;
+
+    let padding = (max_x - min_x).max(max_y - min_y) * 0.25 + 8.0;
+    let left = (min_x - padding).max(0.0) as u32;
+    let top = (min_y - padding).max(0.0) as u32;
+    let right = ((max_x + padding) as u32).min(width);
+    let bottom = ((max_y + padding) as u32).min(height);
+
+    (left < right && top < bottom).then_some((left, top, right, bottom))
+}
+
+/// A PNG of the area of a detected barcode, scaled down to fit in [`THUMBNAIL_SIZE`] pixels,
+/// as a data URI.
+fn thumbnail(image: &DynamicImage, result: &RXingResult) -> Option<String> {
+    let (left, top, right, bottom) = padded_area(result, image.width(), image.height())?;
+
+    let mut crop = image.crop_imm(left, top, right - left, bottom - top);
+    if crop.width() > THUMBNAIL_SIZE || crop.height() > THUMBNAIL_SIZE {
+        crop = crop.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    }
+    let mut png = Vec::new();
+    crop.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .ok()?;
+
+    Some(data_uri::encode_data_uri("image/png", &png))
+}
+
+/// Paints the area of a detected barcode white, so that following detections ignore it.
+///
+/// The result points only mark the finder patterns (or the scan line for 1D codes), not the
+/// symbol edges, so the area spanned by the points is padded before being painted.
+fn mask_result(luma_image: &mut [u8], width: u32, height: u32, result: &RXingResult) {
+    let Some((left, top, right, bottom)) = padded_area(result, width, height) else {
+        return;
+    };
+
+    for y in top..bottom {
+        let row = (y * width) as usize;
+        luma_image[row + left as usize..row + right as usize].fill(u8::MAX);
+    }
+}
+
+/// Renders the file into the images the decoder would scan, without decoding them.
+pub fn render_pages(path: &Path, options: &ScanOptions) -> Result<Vec<DynamicImage>, ScanError> {
+    report(options, "render_pages", Some(path), || {
+        let pages = get_images(path, options)?;
+
+        Ok(pages
+            .into_iter()
+            .filter(|page| page.error.is_none())
+            .map(|page| page.image)
+            .collect())
+    })
+}
+
+/// Decoding hints that callers can set. Unset fields leave the decoder defaults in place.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "camel_case", serde(rename_all = "camelCase"))]
+pub struct HintOptions {
+    /// Barcode formats to look for. No formats is the same as all of them.
+    pub formats: Option<Vec<BarcodeFormat>>,
+    /// Spend more time looking for barcodes. Defaults to `true`.
+    pub try_harder: Option<bool>,
+    /// The image contains only a single barcode, without any border.
+    pub pure_barcode: Option<bool>,
+    /// Character set used to decode the barcode contents, when not declared by the barcode.
+    pub character_set: Option<String>,
+}
+
+/// Creates barcode detection hints from the given options.
+pub fn create_hints(options: &HintOptions) -> HashMap<DecodeHintType, DecodeHintValue> {
+    let mut hints = HashMap::from([(
+        DecodeHintType::TRY_HARDER,
+        DecodeHintValue::TryHarder(options.try_harder.unwrap_or(true)),
+    )]);
+
+    if let Some(formats) = &options.formats {
+        hints.insert(
+            DecodeHintType::POSSIBLE_FORMATS,
+            DecodeHintValue::PossibleFormats(HashSet::from_iter(formats.iter().cloned())),
+        );
+    }
+    if let Some(pure_barcode) = options.pure_barcode {
+        hints.insert(
+            DecodeHintType::PURE_BARCODE,
+            DecodeHintValue::PureBarcode(pure_barcode),
+        );
+    }
+    if let Some(character_set) = &options.character_set {
+        hints.insert(
+            DecodeHintType::CHARACTER_SET,
+            DecodeHintValue::CharacterSet(character_set.clone()),
+        );
+    }
+
+    hints
+}
+
 /// Gets images from the provided file path, handling different formats.
-fn get_images(path: &impl AsRef<Path>) -> Result<Vec<DynamicImage>, String> {
-    let kind = infer::get_from_path(path)
-        .map_err(|_| "Failed to read file".to_string())?
-        .ok_or_else(|| "Unknown file type".to_string())?;
-
-    let mut file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)
-        .map_err(|e| format!("Failed to read file: {}", e))?;
-
-    match kind.mime_type() {
-        "application/pdf" => {
-            extract_images(path).map_err(|e| format!("Failed to extract images from PDF: {:?}", e))
-        }
-        mime_type @ ("image/jpeg" | "image/png" | "image/gif" | "image/webp" | "image/tiff"
-        | "image/bmp") => {
+fn get_images(path: &Path, options: &ScanOptions) -> Result<Vec<Page>, ScanError> {
+    let file = File::open(path)?;
+
+    read_images(file, &with_extension_fallback(path, options))
+}
+
+/// Gets images from the provided reader, handling different formats.
+fn read_images(
+    mut reader: impl Read + Seek,
+    options: &ScanOptions,
+) -> Result<Vec<Page>, ScanError> {
+    let mime_type = sniff_file_type(&mut reader, options)?;
+    check_allowed(&mime_type, options)?;
+
+    match mime_type.as_str() {
+        #[cfg(feature = "pdf")]
+        "application/pdf" => pdf::extract_images(reader, options),
+        mime_type if IMAGE_MIME_TYPES.contains(&mime_type) => {
             let format = ImageFormat::from_mime_type(mime_type).expect("found mime_type");
 
-            image::load_from_memory_with_format(&buffer, format)
-                .map(|img| vec![img])
-                .map_err(|e| format!("Failed to read image: {}", e))
+            let reader = ImageReader::with_format(BufReader::new(reader), format);
+
+            decode_image_file(reader, options).map(|img| vec![img.into()])
+        }
+        "application/zip" => archive::extract_images(reader, options, |_| true),
+        #[cfg(feature = "office")]
+        mime_type if office::MIME_TYPES.contains(&mime_type) => {
+            office::extract_images(reader, options)
         }
-        filetype => Err(format!("Unexpected file type: {filetype}")),
+        #[cfg(feature = "video")]
+        mime_type if video::MIME_TYPES.contains(&mime_type) => {
+            video::extract_frames(reader, options)
+        }
+        filetype => Err(ScanError::unsupported(filetype.to_string())),
+    }
+}
+
+/// Rejects the file types missing from the allowed MIME types, if any.
+fn check_allowed(mime_type: &str, options: &ScanOptions) -> Result<(), ScanError> {
+    let allowed = options.allowed_mime_types.is_empty()
+        || options
+            .allowed_mime_types
+            .iter()
+            .any(|allowed| allowed == mime_type);
+    if !allowed {
+        return Err(ScanError::UnsupportedFileType(mime_type.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Decodes the image of `reader`, unless it has more than `max_pixels` pixels, applying its
+/// orientation when requested.
+fn decode_image_file(
+    reader: ImageReader<impl BufRead + Seek>,
+    options: &ScanOptions,
+) -> Result<DynamicImage, ScanError> {
+    let mut decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    if u64::from(width) * u64::from(height) > options.max_pixels {
+        return Err(ScanError::ImageTooLarge {
+            width,
+            height,
+            max_pixels: options.max_pixels,
+        });
     }
+
+    // a malformed orientation tag is no reason to give up on the pixels
+    let orientation = options
+        .apply_orientation
+        .then(|| decoder.orientation().ok())
+        .flatten();
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    if let Some(orientation) = orientation {
+        image.apply_orientation(orientation);
+    }
+
+    Ok(image)
 }
 
-/// Extracts images from a PDF file using the pdfium library.
-fn extract_images(path: &impl AsRef<Path>) -> Result<Vec<DynamicImage>, PdfiumError> {
-    let pdfium = Pdfium::default();
-    let render_config = PdfRenderConfig::new()
-        .set_target_width(1000)
-        .set_maximum_height(1000)
-        .rotate_if_landscape(PdfPageRenderRotation::Degrees90, true);
+/// Whether files of the given MIME type can be scanned, regardless of the allowed MIME types of
+/// the options.
+pub fn is_supported_mime_type(mime_type: &str) -> bool {
+    #[cfg(feature = "office")]
+    if office::MIME_TYPES.contains(&mime_type) {
+        return true;
+    }
+    #[cfg(feature = "video")]
+    if video::MIME_TYPES.contains(&mime_type) {
+        return true;
+    }
 
-    let document = pdfium.load_pdf_from_file(path, None)?;
-    let mut images: Vec<DynamicImage> = Vec::new();
-    for page in document.pages().iter() {
-        images.push(page.render_with_config(&render_config)?.as_image());
+    #[cfg(feature = "pdf")]
+    if mime_type == "application/pdf" {
+        return true;
     }
 
-    Ok(images)
+    mime_type == "application/zip" || IMAGE_MIME_TYPES.contains(&mime_type)
+}
+
+/// Detects the MIME type from the leading bytes of `reader`, then rewinds it. Falls back to the
+/// MIME type of the options when the bytes are not recognized, unless the options override it.
+fn sniff_file_type(
+    reader: &mut (impl Read + Seek),
+    options: &ScanOptions,
+) -> Result<String, ScanError> {
+    if let Some(mime_type) = &options.mime_override {
+        return Ok(mime_type.clone());
+    }
+
+    let start = reader.stream_position()?;
+    let mut header = Vec::new();
+    reader.take(SNIFF_LEN).read_to_end(&mut header)?;
+    reader.seek(SeekFrom::Start(start))?;
+
+    infer::get(&header)
+        .map(|kind| kind.mime_type().to_string())
+        .or_else(|| options.fallback_mime_type.clone())
+        .ok_or(ScanError::UnknownFileType)
+}
+
+/// Guesses the MIME type of a file from its extension, for the supported types.
+pub fn mime_type_from_extension(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mime_type = match extension.as_str() {
+        #[cfg(feature = "pdf")]
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        #[cfg(feature = "office")]
+        "docx" => office::MIME_TYPES[0],
+        #[cfg(feature = "office")]
+        "xlsx" => office::MIME_TYPES[1],
+        #[cfg(feature = "office")]
+        "pptx" => office::MIME_TYPES[2],
+        #[cfg(feature = "video")]
+        "mp4" => video::MIME_TYPES[0],
+        #[cfg(feature = "video")]
+        "mov" => video::MIME_TYPES[1],
+        #[cfg(feature = "video")]
+        "webm" => video::MIME_TYPES[2],
+        extension => ImageFormat::from_extension(extension)?.to_mime_type(),
+    };
+
+    is_supported_mime_type(mime_type).then(|| mime_type.to_string())
+}
+
+/// Sets the MIME type guessed from the extension of `path` as fallback, unless the options
+/// already have one.
+fn with_extension_fallback<'a>(path: &Path, options: &'a ScanOptions) -> Cow<'a, ScanOptions> {
+    match (&options.fallback_mime_type, mime_type_from_extension(path)) {
+        (None, Some(mime_type)) => Cow::Owned(ScanOptions {
+            fallback_mime_type: Some(mime_type),
+            ..options.clone()
+        }),
+        _ => Cow::Borrowed(options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_images_have_no_barcodes() {
+        let options = ScanOptions {
+            try_mirrored: true,
+            stretch_contrast: true,
+            mask_detected: true,
+            ..ScanOptions::default()
+        };
+
+        for (width, height) in [(0, 0), (0, 5), (5, 0)] {
+            let image = DynamicImage::new_luma8(width, height);
+            assert!(decode_image(&image, &None, &options).is_empty());
+        }
+    }
+
+    #[test]
+    fn exact_dedup_keeps_the_payload() {
+        assert_eq!(DedupMode::Exact.key(" Abc\r\0"), " Abc\r\0");
+        assert_ne!(DedupMode::Exact.key("Abc"), DedupMode::Exact.key("abc"));
+    }
+
+    #[test]
+    fn trim_dedup_strips_whitespace_and_control_characters() {
+        assert_eq!(DedupMode::Trim.key("Abc\r\n"), "Abc");
+        assert_eq!(DedupMode::Trim.key("\0 Abc\0"), "Abc");
+        assert_eq!(DedupMode::Trim.key("\tA b\u{1b}"), "A b");
+        assert_ne!(DedupMode::Trim.key("Abc\r"), DedupMode::Trim.key("abc"));
+    }
+
+    #[test]
+    fn case_fold_dedup_ignores_the_case_too() {
+        assert_eq!(DedupMode::CaseFold.key("AbC\r\0"), "abc");
+        assert_eq!(
+            DedupMode::CaseFold.key(" ÉTÉ\r"),
+            DedupMode::CaseFold.key("été")
+        );
+        assert_ne!(
+            DedupMode::CaseFold.key("a b"),
+            DedupMode::CaseFold.key("ab")
+        );
+    }
 }
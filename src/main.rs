@@ -1,12 +1,275 @@
-use actix_multipart::form::{json::Json as MPJson, tempfile::TempFile, MultipartForm};
-use actix_web::{post, App, HttpResponse, HttpServer, Responder};
-use qr_decoder::{create_hints, process_file};
+use actix_multipart::{
+    form::{
+        bytes::Bytes,
+        json::Json as MPJson,
+        tempfile::{TempFile, TempFileConfig},
+        MultipartForm, MultipartFormConfig,
+    },
+    MultipartError,
+};
+use actix_web::{
+    dev::Service,
+    error::InternalError,
+    get,
+    http::header::{self, HeaderName, HeaderValue},
+    middleware::DefaultHeaders,
+    post, web, App, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageFormat};
+use log::{info, warn};
+#[cfg(feature = "pdf")]
+use qr_decoder::pdfium_version;
+use qr_decoder::{
+    create_hints, decode_data_uri, is_supported_mime_type, mime_type_from_extension,
+    process_bytes_blocking, process_file_blocking, process_file_by_page_blocking,
+    process_files_blocking, process_reader_blocking, render_pages, ColorChannel, CoordinateSpace,
+    DecodePass, DedupMode, ExpectedCount, HintOptions, Overflow, ScanError, ScanOptions,
+    ScanResult,
+};
+use regex::Regex;
 use rxing::BarcodeFormat;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    io::{self, Cursor, Write},
+    iter,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Expose the debug routes, such as `/scanner/render`.
+    #[arg(long, env = "QR_DECODER_DEBUG_ROUTES")]
+    debug_routes: bool,
+    /// Maximum number of pages of the scanned PDFs.
+    #[arg(long, env = "QR_DECODER_MAX_PAGES", default_value_t = ScanOptions::default().max_pages)]
+    max_pages: usize,
+    /// Maximum number of pixels (width times height) of the scanned images.
+    #[arg(long, env = "QR_DECODER_MAX_PIXELS", default_value_t = ScanOptions::default().max_pixels)]
+    max_pixels: u64,
+    /// Maximum number of bytes extracted from the scanned ZIP archives and Office documents.
+    #[arg(long, env = "QR_DECODER_MAX_ARCHIVE_SIZE", default_value_t = ScanOptions::default().max_archive_size)]
+    max_archive_size: u64,
+    /// Maximum number of frames scanned from a video clip.
+    #[cfg(feature = "video")]
+    #[arg(long, env = "QR_DECODER_MAX_FRAMES", default_value_t = ScanOptions::default().max_frames)]
+    max_frames: usize,
+    /// Decode the pages of a file one after the other, instead of in parallel. Saves the
+    /// scheduling overhead on single core machines.
+    #[arg(long, env = "QR_DECODER_SEQUENTIAL")]
+    sequential: bool,
+    /// Comma separated MIME types of the files to accept, such as `image/png,image/jpeg`. Every
+    /// supported type is accepted when not set.
+    #[arg(long, env = "QR_DECODER_ALLOWED_MIME_TYPES", value_delimiter = ',')]
+    allowed_mime_types: Vec<String>,
+    /// Directory of the uploads and of the other intermediate files, such as the frames of video
+    /// clips. Defaults to the system temporary directory.
+    #[arg(long, env = "QR_DECODER_TEMP_DIR")]
+    temp_dir: Option<PathBuf>,
+    /// Token granting access to the admin routes, such as `/admin/scan_directory`, sent as
+    /// `Authorization: Bearer <token>`. The admin routes are disabled when no token is set.
+    #[arg(long, env = "QR_DECODER_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    /// Maximum number of scans running at once. Requests above the limit are rejected with
+    /// `429 Too Many Requests`, unless `--queue-scans` is set. Unlimited when not set.
+    #[arg(long, env = "QR_DECODER_MAX_CONCURRENT_SCANS")]
+    max_concurrent_scans: Option<usize>,
+    /// Wait for a running scan to complete instead of rejecting the requests above
+    /// `--max-concurrent-scans`.
+    #[arg(long, env = "QR_DECODER_QUEUE_SCANS")]
+    queue_scans: bool,
+    /// Keep the uploads of `/scanner/scan` in memory instead of writing them to temporary files,
+    /// saving the disk round trip at the cost of up to 20 MB of memory per upload in flight.
+    #[arg(long, env = "QR_DECODER_MEMORY_UPLOADS")]
+    memory_uploads: bool,
+    /// Maximum time spent decoding each page or image, in milliseconds. The pages taking longer
+    /// are reported without barcodes, flagged as `timed_out`.
+    #[arg(long, env = "QR_DECODER_DECODE_TIMEOUT_MS")]
+    decode_timeout_ms: Option<u64>,
+    /// Decodings of a scan that timed out but still run, beyond which the next pages of that scan
+    /// are decoded without a timeout.
+    #[arg(long, env = "QR_DECODER_MAX_ABANDONED_DECODES", default_value_t = ScanOptions::default().max_abandoned_decodes)]
+    max_abandoned_decodes: usize,
+    /// Path the routes are served under, such as `/api/v1` for `/api/v1/scanner/scan`.
+    #[arg(long, env = "QR_DECODER_ROUTE_PREFIX", default_value = "")]
+    route_prefix: String,
+    /// Header added to every response, such as `Cache-Control: no-store`, unless the route sets
+    /// it. Repeat the option for more headers. Without the option, the headers are read from the
+    /// `QR_DECODER_RESPONSE_HEADERS` variable, one per line, since values can hold any other
+    /// separator.
+    #[arg(long = "response-header", value_parser = parse_header)]
+    response_headers: Vec<(HeaderName, HeaderValue)>,
+    /// Copy the `X-Request-Id` header of the requests to their responses, to trace them across a
+    /// gateway.
+    #[arg(long, env = "QR_DECODER_ECHO_REQUEST_ID")]
+    echo_request_id: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Scan the given files and print the barcodes found, instead of starting the server.
+    Scan {
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Json,
+    Csv,
+}
+
+/// A barcode printed by the `scan` command.
+#[derive(Debug, Serialize)]
+struct ScanRow<'a> {
+    file: String,
+    /// Page number, starting from 1.
+    page: usize,
+    r#type: &'a str,
+    data: &'a str,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(feature = "camel_case", serde(rename_all = "camelCase"))]
 struct Config {
-    formats: Option<Vec<BarcodeFormat>>,
+    #[serde(flatten)]
+    hints: HintOptions,
+    #[serde(default)]
+    mask_detected: bool,
+    #[serde(default)]
+    split_spreads: bool,
+    coordinates: Option<CoordinateSpace>,
+    #[serde(default)]
+    validate_checksums: bool,
+    #[serde(default)]
+    try_mirrored: bool,
+    #[serde(default)]
+    stretch_contrast: bool,
+    #[serde(default)]
+    collect_candidates: bool,
+    min_size: Option<f32>,
+    /// Rejected when parsing the config unless it is a valid regex.
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    payload_filter: Option<Regex>,
+    #[serde(default)]
+    pass_order: Vec<DecodePass>,
+    #[serde(default)]
+    run_all_passes: bool,
+    blank_threshold: Option<f32>,
+    #[serde(default)]
+    channel: ColorChannel,
+    #[serde(default)]
+    apply_orientation: bool,
+    #[serde(default)]
+    provenance: bool,
+    #[serde(default)]
+    include_metadata: bool,
+    #[serde(default)]
+    thumbnails: bool,
+    dedup: Option<DedupMode>,
+    #[serde(default)]
+    dedup_across_formats: bool,
+    rotate_landscape: Option<bool>,
+    #[serde(default)]
+    page_text: bool,
+    #[serde(default)]
+    best_effort: bool,
+    max_results: Option<usize>,
+    #[serde(default)]
+    overflow: Overflow,
+    expect_count: Option<ExpectedCount>,
+    #[serde(default)]
+    format_priority: Vec<BarcodeFormat>,
+    #[serde(default)]
+    format_caps: HashMap<BarcodeFormat, usize>,
+    mime_override: Option<String>,
+    #[serde(default)]
+    render_dpis: Vec<f32>,
+    #[cfg(feature = "video")]
+    frame_interval: Option<f32>,
+}
+
+impl Config {
+    /// Scan options requested by the config, on top of the server `options`.
+    fn scan_options(self, options: &ScanOptions) -> ScanOptions {
+        ScanOptions {
+            mask_detected: self.mask_detected,
+            split_spreads: self.split_spreads,
+            coordinates: self.coordinates,
+            validate_checksums: self.validate_checksums,
+            try_mirrored: self.try_mirrored,
+            stretch_contrast: self.stretch_contrast,
+            collect_candidates: self.collect_candidates,
+            min_size: self.min_size,
+            payload_filter: self.payload_filter,
+            pass_order: self.pass_order,
+            run_all_passes: self.run_all_passes,
+            blank_threshold: self.blank_threshold,
+            channel: self.channel,
+            apply_orientation: self.apply_orientation,
+            provenance: self.provenance,
+            include_metadata: self.include_metadata,
+            thumbnails: self.thumbnails,
+            dedup: self.dedup,
+            dedup_across_formats: self.dedup_across_formats,
+            max_results: self.max_results,
+            overflow: self.overflow,
+            expect_count: self.expect_count,
+            rotate_landscape: self.rotate_landscape.unwrap_or(options.rotate_landscape),
+            page_text: self.page_text,
+            best_effort: self.best_effort,
+            format_priority: self.format_priority,
+            format_caps: self.format_caps,
+            mime_override: self.mime_override,
+            render_dpis: self.render_dpis,
+            #[cfg(feature = "video")]
+            frame_interval: self.frame_interval.unwrap_or(options.frame_interval),
+            ..options.clone()
+        }
+    }
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|pattern| Regex::new(&pattern).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Query of the scan routes.
+#[derive(Debug, Deserialize)]
+struct ScanQuery {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+    /// Set to `ndjson` for one barcode per line, like `Accept: application/x-ndjson` does.
+    format: Option<ResponseFormat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResponseFormat {
+    Json,
+    Ndjson,
 }
 
 #[derive(Debug, MultipartForm)]
@@ -16,30 +279,970 @@ struct UploadForm {
     json: Option<MPJson<Config>>,
 }
 
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "camel_case", serde(rename_all = "camelCase"))]
+struct DataUriRequest {
+    /// The file to scan, as a base64 `data:` URI.
+    data_uri: String,
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "camel_case", serde(rename_all = "camelCase"))]
+struct BatchRequest {
+    files: Vec<BatchFile>,
+    #[serde(flatten)]
+    config: Config,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchFile {
+    /// Name of the file, whose extension tells its type when the content does not.
+    name: String,
+    /// Content of the file, in base64.
+    base64: String,
+}
+
+/// Outcome of the scan of one file of a batch.
+#[derive(Debug, Serialize)]
+struct BatchResult {
+    name: String,
+    result: FileResult,
+}
+
+#[derive(Debug, Deserialize)]
+struct DirectoryRequest {
+    /// Directory of the server to scan.
+    path: PathBuf,
+    #[serde(flatten)]
+    config: Config,
+}
+
+/// Outcome of the scan of one file of a directory.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum FileResult {
+    Scanned(ScanResult),
+    Failed { error: String },
+}
+
+#[cfg(feature = "encode")]
+#[derive(Debug, Deserialize)]
+struct EncodeRequest {
+    /// Text to encode.
+    text: String,
+    format: BarcodeFormat,
+    /// Minimum width of the image, in pixels.
+    #[serde(default = "default_encode_size")]
+    width: u32,
+    /// Minimum height of the image, in pixels.
+    #[serde(default = "default_encode_size")]
+    height: u32,
+}
+
+#[cfg(feature = "encode")]
+fn default_encode_size() -> u32 {
+    300
+}
+
+/// Limits the number of scans running at once, shared by all the workers.
+#[derive(Debug)]
+struct ScanLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+    queue: bool,
+}
+
+impl ScanLimiter {
+    /// Waits for a scan slot, or fails with the response to send when none is free and requests
+    /// are not queued. The slot is released when the permit is dropped, which [`run_scan`] does
+    /// once the scan is over.
+    async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, HttpResponse> {
+        let Some(semaphore) = &self.semaphore else {
+            return Ok(None);
+        };
+
+        if self.queue {
+            let permit = Arc::clone(semaphore)
+                .acquire_owned()
+                .await
+                .expect("the semaphore is never closed");
+            return Ok(Some(permit));
+        }
+        Arc::clone(semaphore)
+            .try_acquire_owned()
+            .map(Some)
+            .map_err(|_| {
+                warn!("Rejected request: too many scans running");
+                HttpResponse::TooManyRequests().json(ErrorResponse {
+                    message: BUSY_MESSAGE.to_string(),
+                })
+            })
+    }
+}
+
+/// Runs the scan on the blocking thread pool, holding its scan slot until it finishes. The scan
+/// keeps running when the client goes away, so the slot must outlive the request.
+async fn run_scan<T: Send + 'static>(
+    permit: Option<OwnedSemaphorePermit>,
+    scan: impl FnOnce() -> T + Send + 'static,
+) -> T {
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        scan()
+    })
+    .await
+    .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// The token expected by the admin routes.
+#[derive(Debug, Clone)]
+struct AdminToken(String);
+
+impl AdminToken {
+    /// Compares `value` with the token in constant time, so that the response time tells neither
+    /// how many leading bytes were right nor the length of the token. It only depends on the
+    /// length of `value`, which the client already knows.
+    fn matches(&self, value: &str) -> bool {
+        let (value, token) = (value.as_bytes(), self.0.as_bytes());
+        // cycling the token reads it once per byte of the value, whatever their lengths
+        value
+            .iter()
+            .zip(token.iter().cycle())
+            .fold(value.len() ^ token.len(), |difference, (a, b)| {
+                difference | usize::from(a ^ b)
+            })
+            == 0
+    }
+}
+
+/// Like [`UploadForm`], keeping the file in memory.
+#[derive(Debug, MultipartForm)]
+struct MemoryUploadForm {
+    #[multipart(limit = "20MB")]
+    file: Bytes,
+    json: Option<MPJson<Config>>,
+}
+
+#[derive(Debug, MultipartForm)]
+struct RenderForm {
+    #[multipart(limit = "20MB")]
+    file: TempFile,
+    json: Option<MPJson<RenderConfig>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RenderConfig {
+    #[serde(default)]
+    format: RenderFormat,
+    /// JPEG quality, from 1 to 100.
+    quality: Option<u8>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RenderFormat {
+    #[default]
+    Png,
+    Jpeg,
+}
+
+impl RenderFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            RenderFormat::Png => "image/png",
+            RenderFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            RenderFormat::Png => "png",
+            RenderFormat::Jpeg => "jpg",
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct ErrorResponse {
     message: String,
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    HttpServer::new(move || App::new().service(scan_file))
-        .bind(("0.0.0.0", 8080))?
-        .run()
-        .await
+/// The headers of the `QR_DECODER_RESPONSE_HEADERS` variable, one per line. Exits like clap does
+/// when one is invalid.
+fn env_response_headers() -> Vec<(HeaderName, HeaderValue)> {
+    let Ok(headers) = env::var("QR_DECODER_RESPONSE_HEADERS") else {
+        return Vec::new();
+    };
+
+    headers
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            parse_header(line).unwrap_or_else(|e| {
+                Args::command()
+                    .error(
+                        ErrorKind::ValueValidation,
+                        format!("invalid header in QR_DECODER_RESPONSE_HEADERS: {e}"),
+                    )
+                    .exit()
+            })
+        })
+        .collect()
+}
+
+/// Parses a `Name: value` header.
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("expected `Name: value`, got `{header}`"))?;
+    let name = HeaderName::try_from(name.trim()).map_err(|e| e.to_string())?;
+    let value = HeaderValue::try_from(value.trim()).map_err(|e| e.to_string())?;
+
+    Ok((name, value))
+}
+
+fn multipart_config(memory_uploads: bool) -> MultipartFormConfig {
+    let config = MultipartFormConfig::default().error_handler(multipart_error);
+    if memory_uploads {
+        // the fields kept in memory are limited to 2 MiB by default
+        config.memory_limit(UPLOAD_LIMIT + 64 * 1024)
+    } else {
+        config
+    }
+}
+
+fn temp_file_config(options: &ScanOptions) -> TempFileConfig {
+    match &options.temp_dir {
+        Some(dir) => TempFileConfig::default().directory(dir),
+        None => TempFileConfig::default(),
+    }
+}
+
+/// Answers the malformed uploads with the reason, such as an invalid `payload_filter`, which the
+/// default response leaves out.
+fn multipart_error(error: MultipartError, _: &HttpRequest) -> actix_web::Error {
+    let message = match &error {
+        MultipartError::Field { source, .. } => format!("{error}: {source}"),
+        error => error.to_string(),
+    };
+    let response = HttpResponse::build(error.status_code()).json(ErrorResponse { message });
+
+    InternalError::from_response(error, response).into()
+}
+
+fn main() -> io::Result<ExitCode> {
+    let mut args = Args::parse();
+    if args.response_headers.is_empty() {
+        args.response_headers = env_response_headers();
+    }
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+
+    let options = ScanOptions {
+        max_pages: args.max_pages,
+        max_pixels: args.max_pixels,
+        max_archive_size: args.max_archive_size,
+        #[cfg(feature = "video")]
+        max_frames: args.max_frames,
+        parallel: !args.sequential,
+        allowed_mime_types: args.allowed_mime_types.clone(),
+        temp_dir: args.temp_dir.clone(),
+        decode_timeout: args.decode_timeout_ms.map(Duration::from_millis),
+        max_abandoned_decodes: args.max_abandoned_decodes,
+        ..Default::default()
+    };
+
+    match &args.command {
+        Some(Command::Scan { format, files }) => scan_files(files, *format, &options),
+        None => actix_web::rt::System::new()
+            .block_on(serve(args, options))
+            .map(|_| ExitCode::SUCCESS),
+    }
 }
 
+/// Scans the files from the command line, printing the barcodes to the standard output.
+///
+/// Files that cannot be scanned are reported on the standard error and make the command fail.
+fn scan_files(
+    files: &[PathBuf],
+    format: OutputFormat,
+    options: &ScanOptions,
+) -> io::Result<ExitCode> {
+    let hints = create_hints(&HintOptions::default());
+
+    let mut scanned = Vec::new();
+    let mut failed = false;
+    for file in files {
+        match process_file_by_page_blocking(file, Some(hints.clone()), options) {
+            Ok(pages) => scanned.push((file.display().to_string(), pages)),
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                failed = true;
+            }
+        }
+    }
+
+    let rows = scanned.iter().flat_map(|(file, pages)| {
+        pages.iter().enumerate().flat_map(move |(index, page)| {
+            page.barcodes.iter().map(move |barcode| ScanRow {
+                file: file.clone(),
+                page: index + 1,
+                r#type: &barcode.r#type,
+                data: &barcode.data,
+            })
+        })
+    });
+
+    let stdout = io::stdout().lock();
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(stdout, &rows.collect::<Vec<_>>())?;
+            println!();
+        }
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(stdout);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+async fn serve(args: Args, options: ScanOptions) -> io::Result<()> {
+    let limiter = web::Data::new(ScanLimiter {
+        semaphore: args
+            .max_concurrent_scans
+            .map(|permits| Arc::new(Semaphore::new(permits))),
+        queue: args.queue_scans,
+    });
+
+    let prefix = match args.route_prefix.trim_matches('/') {
+        "" => String::new(),
+        prefix => format!("/{prefix}"),
+    };
+
+    HttpServer::new(move || {
+        let mut routes = web::scope(&prefix)
+            .service(scan_data_uri)
+            .service(
+                web::resource("/scanner/scan_batch")
+                    .app_data(web::JsonConfig::default().limit(BATCH_JSON_LIMIT))
+                    .route(web::post().to(scan_batch)),
+            )
+            .service(version);
+        routes = if args.memory_uploads {
+            routes.service(scan_file_in_memory)
+        } else {
+            routes.service(scan_file)
+        };
+        if args.debug_routes {
+            routes = routes.service(render_file);
+        }
+        #[cfg(feature = "encode")]
+        {
+            routes = routes.service(encode_text);
+        }
+        if let Some(token) = &args.admin_token {
+            routes = routes
+                .app_data(web::Data::new(AdminToken(token.clone())))
+                .service(scan_directory);
+        }
+
+        let mut headers = DefaultHeaders::new();
+        for header in &args.response_headers {
+            headers = headers.add(header.clone());
+        }
+        let echo_request_id = args.echo_request_id;
+
+        App::new()
+            .app_data(web::Data::new(options.clone()))
+            .app_data(limiter.clone())
+            .app_data(web::JsonConfig::default().limit(JSON_LIMIT))
+            .app_data(multipart_config(args.memory_uploads))
+            .app_data(temp_file_config(&options))
+            .wrap(headers)
+            .wrap_fn(move |request, service| {
+                let request_id = request
+                    .headers()
+                    .get(REQUEST_ID)
+                    .filter(|_| echo_request_id)
+                    .cloned();
+                let response = service.call(request);
+                async move {
+                    let mut response = response.await?;
+                    if let Some(request_id) = request_id {
+                        response.headers_mut().insert(REQUEST_ID, request_id);
+                    }
+                    Ok(response)
+                }
+            })
+            .service(routes)
+    })
+    .bind(("0.0.0.0", 8080))?
+    .run()
+    .await
+}
+
+#[post("/scanner/scan")]
+async fn scan_file(
+    MultipartForm(form): MultipartForm<UploadForm>,
+    request: HttpRequest,
+    query: web::Query<ScanQuery>,
+    options: web::Data<ScanOptions>,
+    limiter: web::Data<ScanLimiter>,
+) -> impl Responder {
+    let permit = match limiter.acquire().await {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+    let config = form.json.map(|json| json.into_inner()).unwrap_or_default();
+    let hints = create_hints(&config.hints);
+    let options = upload_options(
+        form.file.file_name.as_deref(),
+        form.file
+            .content_type
+            .as_ref()
+            .map(|mime| mime.essence_str()),
+        config,
+        &query,
+        &options,
+    );
+
+    // the upload is deleted when dropped, it must last as long as the scan
+    let file = form.file.file;
+    let scan = move || process_file_blocking(file.path(), Some(hints), &options);
+    match run_scan(permit, scan).await {
+        Ok(result) => scan_response(&request, &query, result),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Like [`scan_file`], served instead of it with `--memory-uploads`.
 #[post("/scanner/scan")]
-async fn scan_file(MultipartForm(form): MultipartForm<UploadForm>) -> impl Responder {
-    let file_path = form.file.file.path();
-    let hints = form
-        .json
-        .and_then(|some| create_hints(some.formats.clone()));
-
-    match process_file(file_path, hints).await {
-        Ok(barcodes) => HttpResponse::Ok().json(barcodes),
+async fn scan_file_in_memory(
+    MultipartForm(form): MultipartForm<MemoryUploadForm>,
+    request: HttpRequest,
+    query: web::Query<ScanQuery>,
+    options: web::Data<ScanOptions>,
+    limiter: web::Data<ScanLimiter>,
+) -> impl Responder {
+    let permit = match limiter.acquire().await {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+    let config = form.json.map(|json| json.into_inner()).unwrap_or_default();
+    let hints = create_hints(&config.hints);
+    let options = upload_options(
+        form.file.file_name.as_deref(),
+        form.file
+            .content_type
+            .as_ref()
+            .map(|mime| mime.essence_str()),
+        config,
+        &query,
+        &options,
+    );
+
+    let data = form.file.data;
+    let scan = move || process_reader_blocking(Cursor::new(data), Some(hints), &options);
+    match run_scan(permit, scan).await {
+        Ok(result) => scan_response(&request, &query, result),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Scan options for an upload, on top of the server `options`.
+fn upload_options(
+    file_name: Option<&str>,
+    content_type: Option<&str>,
+    config: Config,
+    query: &ScanQuery,
+    options: &ScanOptions,
+) -> ScanOptions {
+    // the uploaded bytes have no extension, the client tells what it sent
+    let fallback_mime_type = file_name
+        .and_then(|name| mime_type_from_extension(Path::new(name)))
+        .or_else(|| {
+            content_type
+                .filter(|mime_type| is_supported_mime_type(mime_type))
+                .map(str::to_string)
+        });
+
+    ScanOptions {
+        fallback_mime_type,
+        offset: query.offset,
+        limit: query.limit,
+        ..config.scan_options(options)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct VersionResponse {
+    version: &'static str,
+    #[cfg(feature = "pdf")]
+    pdfium: PdfiumStatus,
+}
+
+/// The pdfium release the server was built for, or why the library could not be loaded.
+#[cfg(feature = "pdf")]
+#[derive(Debug, Clone, Serialize)]
+struct PdfiumStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Reports the versions of the server and of its native dependencies, to check a rollout.
+/// pdfium is only loaded by the first call.
+#[get("/version")]
+async fn version() -> impl Responder {
+    static VERSION: OnceLock<VersionResponse> = OnceLock::new();
+
+    let version = VERSION.get_or_init(|| VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        #[cfg(feature = "pdf")]
+        pdfium: match pdfium_version() {
+            Ok(version) => PdfiumStatus {
+                version: Some(version),
+                error: None,
+            },
+            Err(e) => PdfiumStatus {
+                version: None,
+                error: Some(e.to_string()),
+            },
+        },
+    });
+
+    HttpResponse::Ok().json(version)
+}
+
+/// Scans a file sent as a data URI, like the images captured by browsers.
+#[post("/scanner/scan_data_uri")]
+async fn scan_data_uri(
+    body: web::Json<DataUriRequest>,
+    request: HttpRequest,
+    query: web::Query<ScanQuery>,
+    options: web::Data<ScanOptions>,
+    limiter: web::Data<ScanLimiter>,
+) -> impl Responder {
+    let permit = match limiter.acquire().await {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+
+    let DataUriRequest { data_uri, config } = body.into_inner();
+    let (mime_type, bytes) = match decode_data_uri(&data_uri) {
+        Ok(decoded) => decoded,
+        Err(e) => return error_response(e),
+    };
+    if bytes.len() > UPLOAD_LIMIT {
+        return HttpResponse::PayloadTooLarge().json(ErrorResponse {
+            message: format!("The file is larger than {UPLOAD_LIMIT} bytes"),
+        });
+    }
+
+    let hints = create_hints(&config.hints);
+    let options = ScanOptions {
+        fallback_mime_type: Some(mime_type),
+        offset: query.offset,
+        limit: query.limit,
+        ..config.scan_options(&options)
+    };
+
+    let scan = move || process_bytes_blocking(bytes, Some(hints), &options);
+    match run_scan(permit, scan).await {
+        Ok(result) => scan_response(&request, &query, result),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Scans several files sent in base64 in the same JSON body, in parallel, returning their
+/// results in the same order. A file that fails to scan does not fail the others.
+///
+/// Each file takes a scan slot of its own. When requests are not queued, the files finding no
+/// free slot fail like the other errors.
+async fn scan_batch(
+    body: web::Json<BatchRequest>,
+    options: web::Data<ScanOptions>,
+    limiter: web::Data<ScanLimiter>,
+) -> impl Responder {
+    let BatchRequest { files, config } = body.into_inner();
+    if files.len() > BATCH_FILES {
+        return HttpResponse::BadRequest().json(ErrorResponse {
+            message: format!(
+                "The batch has {} files, more than the {BATCH_FILES} allowed",
+                files.len()
+            ),
+        });
+    }
+
+    let decoded: Vec<(String, Result<Vec<u8>, String>)> = files
+        .into_iter()
+        .map(|file| {
+            let bytes = STANDARD
+                .decode(file.base64.trim())
+                .map_err(|e| format!("Invalid base64: {e}"))
+                .and_then(|bytes| {
+                    if bytes.len() > UPLOAD_LIMIT {
+                        Err(format!("The file is larger than {UPLOAD_LIMIT} bytes"))
+                    } else {
+                        Ok(bytes)
+                    }
+                });
+            (file.name, bytes)
+        })
+        .collect();
+    let total: usize = decoded
+        .iter()
+        .filter_map(|(_, bytes)| bytes.as_ref().ok())
+        .map(Vec::len)
+        .sum();
+    if total > BATCH_LIMIT {
+        return HttpResponse::PayloadTooLarge().json(ErrorResponse {
+            message: format!("The files are larger than {BATCH_LIMIT} bytes together"),
+        });
+    }
+
+    let hints = create_hints(&config.hints);
+    let options = config.scan_options(&options);
+    let count = decoded.len();
+    // dropping the set when the client goes away cancels the scans still waiting for a slot
+    let mut scans = JoinSet::new();
+    for (index, (name, bytes)) in decoded.into_iter().enumerate() {
+        let hints = hints.clone();
+        let limiter = limiter.clone();
+        let options = ScanOptions {
+            fallback_mime_type: mime_type_from_extension(Path::new(&name)),
+            ..options.clone()
+        };
+        scans.spawn_local(async move {
+            let result = match bytes {
+                Ok(bytes) => match limiter.acquire().await {
+                    Ok(permit) => {
+                        let scan = move || process_bytes_blocking(bytes, Some(hints), &options);
+                        match run_scan(permit, scan).await {
+                            Ok(result) => FileResult::Scanned(result),
+                            Err(e) => FileResult::Failed {
+                                error: e.to_string(),
+                            },
+                        }
+                    }
+                    Err(_) => FileResult::Failed {
+                        error: BUSY_MESSAGE.to_string(),
+                    },
+                },
+                Err(error) => FileResult::Failed { error },
+            };
+            (index, BatchResult { name, result })
+        });
+    }
+
+    let mut results: Vec<Option<BatchResult>> = iter::repeat_with(|| None).take(count).collect();
+    while let Some(scanned) = scans.join_next().await {
+        let (index, result) = scanned.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        results[index] = Some(result);
+    }
+
+    HttpResponse::Ok().json(results.into_iter().flatten().collect::<Vec<_>>())
+}
+
+/// Responds with the scan result, or with one barcode per line when NDJSON is requested.
+///
+/// NDJSON only carries the barcodes, the rest of the result goes in headers: the total number of
+/// barcodes in `X-Total-Count`, `X-Truncated: true` when `max_results` cut the list, and in
+/// `X-Incomplete-Pages` the number of pages skipped, timed out or failed, when there are any. The
+/// details of the pages are left out, they need the JSON response.
+fn scan_response(request: &HttpRequest, query: &ScanQuery, result: ScanResult) -> HttpResponse {
+    let ndjson = match query.format {
+        Some(format) => format == ResponseFormat::Ndjson,
+        None => request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains(NDJSON_CONTENT_TYPE)),
+    };
+    if !ndjson {
+        return HttpResponse::Ok().json(result);
+    }
+
+    let mut body = Vec::new();
+    for barcode in &result.barcodes {
+        // serializing plain data into memory cannot fail
+        serde_json::to_writer(&mut body, barcode).expect("serializable barcode");
+        body.push(b'\n');
+    }
+
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(NDJSON_CONTENT_TYPE)
+        .insert_header(("X-Total-Count", result.total));
+    if result.truncated {
+        response.insert_header(("X-Truncated", "true"));
+    }
+    let incomplete = result
+        .pages
+        .iter()
+        .filter(|page| page.skipped || page.timed_out || page.error.is_some())
+        .count();
+    if incomplete > 0 {
+        response.insert_header(("X-Incomplete-Pages", incomplete));
+    }
+
+    response.body(body)
+}
+
+/// Admin route scanning every supported file of a server directory and its subdirectories,
+/// keyed by path relative to the directory. Symbolic links are not followed.
+#[post("/admin/scan_directory")]
+async fn scan_directory(
+    request: HttpRequest,
+    body: web::Json<DirectoryRequest>,
+    options: web::Data<ScanOptions>,
+    token: web::Data<AdminToken>,
+    limiter: web::Data<ScanLimiter>,
+) -> impl Responder {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| token.matches(value));
+    if !authorized {
+        return HttpResponse::Unauthorized().json(ErrorResponse {
+            message: "Missing or invalid admin token".to_string(),
+        });
+    }
+
+    let permit = match limiter.acquire().await {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+
+    let DirectoryRequest { path, config } = body.into_inner();
+    let hints = create_hints(&config.hints);
+    let options = config.scan_options(&options);
+
+    let walk_root = path.clone();
+    let files = match web::block(move || list_files(&walk_root)).await {
+        Ok(Ok(files)) => files,
+        Ok(Err(e)) => {
+            return HttpResponse::BadRequest().json(ErrorResponse {
+                message: format!("Failed to read directory {}: {}", path.display(), e),
+            })
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(ErrorResponse {
+                message: e.to_string(),
+            })
+        }
+    };
+    info!("Scanning {} files of {}", files.len(), path.display());
+
+    let scanned = files.clone();
+    let scan = move || process_files_blocking(&scanned, Some(hints), &options);
+    let results = run_scan(permit, scan).await;
+    let summary: BTreeMap<String, FileResult> = files
+        .iter()
+        .zip(results)
+        .filter_map(|(file, result)| {
+            let result = match result {
+                Ok(result) => FileResult::Scanned(result),
+                // other files may share the directory, only the supported ones are of interest
+                Err(ScanError::UnknownFileType | ScanError::UnsupportedFileType(_)) => return None,
+                Err(e) => FileResult::Failed {
+                    error: e.to_string(),
+                },
+            };
+            let relative = file.strip_prefix(&path).unwrap_or(file);
+            Some((relative.display().to_string(), result))
+        })
+        .collect();
+
+    HttpResponse::Ok().json(summary)
+}
+
+/// Lists the regular files of `directory` and of its subdirectories, in path order.
+fn list_files(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                directories.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Debug route returning the images the decoder would scan: a single image for one page, a ZIP of
+/// images otherwise. Images are PNGs unless JPEG is requested.
+#[post("/scanner/render")]
+async fn render_file(
+    MultipartForm(form): MultipartForm<RenderForm>,
+    options: web::Data<ScanOptions>,
+    limiter: web::Data<ScanLimiter>,
+) -> impl Responder {
+    let permit = match limiter.acquire().await {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+    let file = form.file.file;
+    let config = form.json.map(|json| json.into_inner()).unwrap_or_default();
+    let options = options.into_inner();
+
+    // rendering is CPU bound, keep it off the async workers
+    let rendered = run_scan(permit, move || {
+        render_pages(file.path(), &options).map(|images| encode_pages(&images, &config))
+    })
+    .await;
+
+    match rendered {
+        Ok(Ok((content_type, body))) => HttpResponse::Ok().content_type(content_type).body(body),
+        Err(e) => error_response(e),
+        Ok(Err(message)) => HttpResponse::InternalServerError().json(ErrorResponse { message }),
+    }
+}
+
+/// Encodes the text of the request into a PNG barcode image.
+#[cfg(feature = "encode")]
+#[post("/scanner/encode")]
+async fn encode_text(body: web::Json<EncodeRequest>) -> impl Responder {
+    let request = body.into_inner();
+    let encoded = web::block(move || {
+        qr_decoder::encode_barcode(&request.text, request.format, request.width, request.height)
+            .map(|image| encode_image(&image, &RenderConfig::default()))
+    })
+    .await;
+
+    match encoded {
+        Ok(Ok(Ok(body))) => HttpResponse::Ok()
+            .content_type(RenderFormat::Png.content_type())
+            .body(body),
+        Ok(Err(e)) => error_response(e),
+        Ok(Ok(Err(message))) => HttpResponse::InternalServerError().json(ErrorResponse { message }),
         Err(e) => HttpResponse::InternalServerError().json(ErrorResponse {
             message: e.to_string(),
         }),
     }
 }
+
+/// Maps a scan error to its HTTP response, logging the rejected uploads.
+fn error_response(error: ScanError) -> HttpResponse {
+    let mut response = match &error {
+        ScanError::UnknownFileType => {
+            warn!("Rejected upload: unknown file type");
+            HttpResponse::UnsupportedMediaType()
+        }
+        ScanError::PdfNotCompiled => {
+            warn!("Rejected upload: PDF support is not compiled in");
+            HttpResponse::UnsupportedMediaType()
+        }
+        ScanError::UnsupportedFileType(mime_type) => {
+            warn!("Rejected upload: unsupported file type {mime_type}");
+            HttpResponse::UnsupportedMediaType()
+        }
+        ScanError::Encode(_) | ScanError::DataUri(_) | ScanError::RenderDpis(_) => {
+            HttpResponse::BadRequest()
+        }
+        ScanError::EmptyDocument
+        | ScanError::PageOutOfRange { .. }
+        | ScanError::UnexpectedCount { .. }
+        | ScanError::TooManyBarcodes { .. } => HttpResponse::UnprocessableEntity(),
+        ScanError::TooManyPages { .. }
+        | ScanError::ImageTooLarge { .. }
+        | ScanError::ArchiveTooLarge { .. } => HttpResponse::PayloadTooLarge(),
+        #[cfg(feature = "pdf")]
+        ScanError::PdfiumUnavailable(_) => {
+            log::error!("{error}, it must be in the working directory or installed system wide");
+            HttpResponse::ServiceUnavailable()
+        }
+        _ => HttpResponse::InternalServerError(),
+    };
+
+    response.json(ErrorResponse {
+        message: error.to_string(),
+    })
+}
+
+const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Error message of the scans finding no free slot.
+const BUSY_MESSAGE: &str = "Too many scans running, retry later";
+
+/// Maximum size of the uploaded files, matching the limit of the multipart forms.
+const UPLOAD_LIMIT: usize = 20_000_000;
+
+/// Maximum size of the JSON bodies, enough for an upload encoded in a data URI.
+const JSON_LIMIT: usize = UPLOAD_LIMIT / 3 * 4 + 64 * 1024;
+
+/// Maximum number of files of a batch.
+const BATCH_FILES: usize = 20;
+
+/// Maximum size of the files of a batch together.
+const BATCH_LIMIT: usize = 5 * UPLOAD_LIMIT;
+
+/// Maximum size of the JSON bodies of the batches, enough for their files encoded in base64.
+const BATCH_JSON_LIMIT: usize = BATCH_LIMIT / 3 * 4 + 64 * 1024;
+
+/// Default quality of the JPEG renders.
+const JPEG_QUALITY: u8 = 75;
+
+/// Encodes the rendered pages, returning the content type along with the body.
+fn encode_pages(
+    images: &[DynamicImage],
+    config: &RenderConfig,
+) -> Result<(&'static str, Vec<u8>), String> {
+    if let [image] = images {
+        return encode_image(image, config).map(|body| (config.format.content_type(), body));
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // the images are already compressed
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (index, image) in images.iter().enumerate() {
+        let name = format!("page-{:03}.{}", index + 1, config.format.extension());
+        zip.start_file(name, options)
+            .map_err(|e| format!("Failed to write archive: {}", e))?;
+        zip.write_all(&encode_image(image, config)?)
+            .map_err(|e| format!("Failed to write archive: {}", e))?;
+    }
+    let archive = zip
+        .finish()
+        .map_err(|e| format!("Failed to write archive: {}", e))?;
+
+    Ok(("application/zip", archive.into_inner()))
+}
+
+fn encode_image(image: &DynamicImage, config: &RenderConfig) -> Result<Vec<u8>, String> {
+    let mut buffer = Cursor::new(Vec::new());
+    let encoded = match config.format {
+        RenderFormat::Png => image.write_to(&mut buffer, ImageFormat::Png),
+        RenderFormat::Jpeg => {
+            let quality = config.quality.unwrap_or(JPEG_QUALITY).clamp(1, 100);
+            // JPEG has no alpha channel
+            image
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut buffer, quality))
+        }
+    };
+    encoded.map_err(|e| format!("Failed to encode image: {}", e))?;
+
+    Ok(buffer.into_inner())
+}
@@ -0,0 +1,26 @@
+//! Images embedded in Office Open XML documents (docx, xlsx, pptx).
+
+use crate::{archive, Page, ScanError, ScanOptions};
+use std::io::{Read, Seek};
+
+/// MIME types of the supported Office documents.
+pub(crate) const MIME_TYPES: [&str; 3] = [
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+];
+
+/// Folders of the package holding the embedded media.
+const MEDIA_FOLDERS: [&str; 3] = ["word/media/", "xl/media/", "ppt/media/"];
+
+/// Extracts the images embedded in the document, in entry name order.
+///
+/// Media that cannot be decoded, such as EMF drawings, is skipped.
+pub(crate) fn extract_images(
+    reader: impl Read + Seek,
+    options: &ScanOptions,
+) -> Result<Vec<Page>, ScanError> {
+    archive::extract_images(reader, options, |name| {
+        MEDIA_FOLDERS.iter().any(|folder| name.starts_with(folder))
+    })
+}
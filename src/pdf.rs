@@ -0,0 +1,170 @@
+//! Pages of PDF documents, rendered with the pdfium library.
+
+use crate::{keep_going, Page, ScanError, ScanOptions};
+use pdfium_render::prelude::*;
+use std::io::{Read, Seek};
+
+/// Extracts images from a PDF file using the pdfium library.
+pub(crate) fn extract_images(
+    reader: impl Read + Seek,
+    options: &ScanOptions,
+) -> Result<Vec<Page>, ScanError> {
+    let pdfium = pdfium()?;
+    let document = pdfium.load_pdf_from_reader(reader, None)?;
+    let pages = document.pages().len() as usize;
+    if pages == 0 {
+        return Err(ScanError::EmptyDocument);
+    }
+    if pages > options.max_pages {
+        return Err(ScanError::TooManyPages {
+            pages,
+            max_pages: options.max_pages,
+        });
+    }
+
+    let render_configs = render_configs(options)?;
+    let mut rendered = Vec::with_capacity(pages);
+    for page in document.pages().iter() {
+        match render_page(&page, &render_configs, options) {
+            Ok(page) => rendered.push(page),
+            Err(e) => keep_going(&mut rendered, e, options)?,
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Extracts the image of the page at `index` from a PDF file, leaving the other pages alone.
+pub(crate) fn extract_page(
+    reader: impl Read + Seek,
+    index: usize,
+    options: &ScanOptions,
+) -> Result<Page, ScanError> {
+    let pdfium = pdfium()?;
+    let document = pdfium.load_pdf_from_reader(reader, None)?;
+    let pages = document.pages().len() as usize;
+    if index >= pages {
+        return Err(ScanError::PageOutOfRange { index, pages });
+    }
+
+    let page = document.pages().get(index as u16)?;
+    render_page(&page, &render_configs(options)?, options)
+}
+
+/// Maximum number of resolutions to render each page at.
+const MAX_RENDER_DPIS: usize = 4;
+
+/// Maximum resolution to render the pages at, in dots per inch.
+const MAX_RENDER_DPI: f32 = 1200.0;
+
+/// The configurations to render each page with, one for each of the requested resolutions.
+fn render_configs(options: &ScanOptions) -> Result<Vec<PdfRenderConfig>, ScanError> {
+    let configure = |render_config: PdfRenderConfig| {
+        // payment codes are sometimes stamped as annotations or form fields rather than page
+        // content
+        let render_config = render_config
+            .render_annotations(true)
+            .render_form_data(true);
+
+        if options.rotate_landscape {
+            render_config.rotate_if_landscape(PdfPageRenderRotation::Degrees90, true)
+        } else {
+            render_config
+        }
+    };
+
+    if options.render_dpis.is_empty() {
+        return Ok(vec![configure(
+            PdfRenderConfig::new()
+                .set_target_width(1000)
+                .set_maximum_height(1000),
+        )]);
+    }
+
+    if options.render_dpis.len() > MAX_RENDER_DPIS {
+        return Err(ScanError::RenderDpis(format!(
+            "{} resolutions, more than the {MAX_RENDER_DPIS} allowed",
+            options.render_dpis.len()
+        )));
+    }
+    if let Some(dpi) = options
+        .render_dpis
+        .iter()
+        .find(|dpi| !(dpi.is_finite() && **dpi > 0.0 && **dpi <= MAX_RENDER_DPI))
+    {
+        return Err(ScanError::RenderDpis(format!(
+            "{dpi} DPI, expected more than 0 and at most {MAX_RENDER_DPI}"
+        )));
+    }
+
+    Ok(options
+        .render_dpis
+        .iter()
+        .map(|dpi| configure(PdfRenderConfig::new().scale_page_by_factor(dpi / 72.0)))
+        .collect())
+}
+
+/// Rejects the pages that would render to more than the allowed pixels at one of the requested
+/// resolutions, before pdfium allocates them.
+fn check_render_size(page: &PdfPage, options: &ScanOptions) -> Result<(), ScanError> {
+    let (width, height) = (page.width().to_inches(), page.height().to_inches());
+    for dpi in &options.render_dpis {
+        let (width, height) = ((width * dpi).ceil() as u32, (height * dpi).ceil() as u32);
+        if u64::from(width) * u64::from(height) > options.max_pixels {
+            return Err(ScanError::ImageTooLarge {
+                width,
+                height,
+                max_pixels: options.max_pixels,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn render_page(
+    page: &PdfPage,
+    render_configs: &[PdfRenderConfig],
+    options: &ScanOptions,
+) -> Result<Page, ScanError> {
+    check_render_size(page, options)?;
+
+    let mut images = render_configs
+        .iter()
+        .map(|render_config| Ok(page.render_with_config(render_config)?.as_image()))
+        .collect::<Result<Vec<_>, ScanError>>()?
+        .into_iter();
+    let image = images.next().expect("at least one render configuration");
+
+    // comparing the longest sides holds whether or not the page was rotated
+    let inches = page.width().to_inches().max(page.height().to_inches());
+    let dpi = (inches > 0.0).then(|| image.width().max(image.height()) as f32 / inches);
+
+    Ok(Page {
+        image,
+        dpi,
+        time: None,
+        renders: images.collect(),
+        text: options
+            .page_text
+            .then(|| page.text().map(|text| text.all()))
+            .transpose()?,
+        error: None,
+    })
+}
+
+/// The pdfium release the bindings target, such as `6611`, after checking that the library loads.
+pub fn pdfium_version() -> Result<String, ScanError> {
+    let version = format!("{:?}", pdfium()?.bindings().version());
+
+    Ok(version.trim_start_matches('V').to_lowercase())
+}
+
+/// Binds to the pdfium library of the working directory, falling back to the system one, as
+/// `Pdfium::default` does, but without panicking when neither can be loaded.
+fn pdfium() -> Result<Pdfium, ScanError> {
+    Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("./"))
+        .or_else(|_| Pdfium::bind_to_system_library())
+        .map(Pdfium::new)
+        .map_err(ScanError::PdfiumUnavailable)
+}
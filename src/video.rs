@@ -0,0 +1,108 @@
+//! Frames of video clips, extracted with the ffmpeg command line tool.
+
+use crate::{decode_image_file, keep_going, Page, ScanError, ScanOptions};
+use image::ImageReader;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// MIME types of the supported video containers.
+pub(crate) const MIME_TYPES: [&str; 3] = ["video/mp4", "video/quicktime", "video/webm"];
+
+/// Time given to ffmpeg to extract the frames of a clip, before it is killed.
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Interval between the checks of whether ffmpeg is done.
+const FFMPEG_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Extracts a frame every `frame_interval` seconds, up to `max_frames`, in playback order, each
+/// with its time.
+///
+/// The clip is copied to a temporary directory first, since ffmpeg cannot read MP4 files whose
+/// index comes last from a pipe.
+pub(crate) fn extract_frames(
+    mut reader: impl Read + Seek,
+    options: &ScanOptions,
+) -> Result<Vec<Page>, ScanError> {
+    if !options.frame_interval.is_finite() || options.frame_interval <= 0.0 {
+        return Err(ScanError::Video(format!(
+            "the frame interval must be positive, got {}",
+            options.frame_interval
+        )));
+    }
+
+    let dir = match &options.temp_dir {
+        Some(temp_dir) => tempfile::tempdir_in(temp_dir)?,
+        None => tempfile::tempdir()?,
+    };
+    let input = dir.path().join("input");
+    io::copy(&mut reader, &mut File::create(&input)?)?;
+
+    let mut child = Command::new("ffmpeg")
+        .args(["-nostdin", "-v", "error", "-i"])
+        .arg(&input)
+        .arg("-vf")
+        .arg(format!("fps=1/{}", options.frame_interval))
+        .arg("-frames:v")
+        .arg(options.max_frames.to_string())
+        .arg(dir.path().join("frame_%06d.png"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| ScanError::Video(format!("failed to run ffmpeg: {e}")))?;
+
+    // read on the side, so that a chatty ffmpeg cannot block on a full pipe
+    let mut stderr = child.stderr.take().expect("piped stderr");
+    let errors = thread::spawn(move || {
+        let mut errors = String::new();
+        let _ = stderr.read_to_string(&mut errors);
+        errors
+    });
+
+    // a malformed clip must not hold the scan forever
+    let deadline = Instant::now() + FFMPEG_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(ScanError::Video(format!(
+                "ffmpeg took more than {} seconds",
+                FFMPEG_TIMEOUT.as_secs()
+            )));
+        }
+        thread::sleep(FFMPEG_POLL_INTERVAL);
+    };
+    if !status.success() {
+        let errors = errors.join().unwrap_or_default();
+        return Err(ScanError::Video(errors.trim().to_string()));
+    }
+
+    let mut frames: Vec<_> = fs::read_dir(dir.path())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    frames.retain(|path| path.extension().is_some_and(|extension| extension == "png"));
+    // the frame numbers are zero padded, so the name order is the playback order
+    frames.sort();
+
+    let mut pages = Vec::new();
+    for (index, path) in frames.iter().enumerate() {
+        match ImageReader::open(path)
+            .map_err(ScanError::from)
+            .and_then(|reader| decode_image_file(reader, options))
+        {
+            Ok(image) => pages.push(image.into()),
+            Err(e) => keep_going(&mut pages, e, options)?,
+        }
+        pages[index].time = Some(index as f32 * options.frame_interval);
+    }
+
+    Ok(pages)
+}